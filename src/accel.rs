@@ -0,0 +1,44 @@
+use crate::aabb::AABB;
+use crate::bvh::BVH;
+use crate::kdtree::KDNode;
+use crate::tracer::Ray;
+use crate::triangle::Triangle;
+
+use std::str::FromStr;
+
+// ray queries the tracer needs from a spatial index over Obj::triangles
+pub trait Accel: Send + Sync {
+    // closest triangle along r closer than tmax, as (index, distance)
+    fn closest_hit(&self, triangles: &[Triangle], r: &Ray, tmax: f64) -> Option<(usize, f64)>;
+
+    // whether anything at all blocks r before tmax, for shadow rays
+    fn any_hit(&self, triangles: &[Triangle], r: &Ray, tmax: f64) -> bool;
+
+    // one line description for the load log
+    fn summary(&self) -> String;
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum AccelKind {
+    KdTree,
+    Bvh,
+}
+
+impl FromStr for AccelKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<AccelKind, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "kdtree" | "kd-tree" | "kd" => Ok(AccelKind::KdTree),
+            "bvh" => Ok(AccelKind::Bvh),
+            _ => Err(format!("unknown acceleration structure: {}", s)),
+        }
+    }
+}
+
+pub fn build(kind: AccelKind, triangles: &[Triangle], aabb: &AABB) -> Box<dyn Accel> {
+    match kind {
+        AccelKind::KdTree => Box::new(KDNode::new(triangles, aabb)),
+        AccelKind::Bvh => Box::new(BVH::new(triangles)),
+    }
+}
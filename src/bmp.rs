@@ -0,0 +1,328 @@
+use crate::image::{self, Image};
+use crate::vec4::Vec4;
+
+// windows bitmap reader: 1, 4 and 8 bit palettes (raw or rle), 16, 24 and
+// 32 bit colour with or without bitfield masks
+// https://learn.microsoft.com/en-us/windows/win32/gdi/bitmap-storage
+
+const BI_RGB: u32 = 0;
+const BI_RLE8: u32 = 1;
+const BI_RLE4: u32 = 2;
+const BI_BITFIELDS: u32 = 3;
+const BI_ALPHABITFIELDS: u32 = 6;
+
+pub fn read_bmp(data: &[u8]) -> Result<Image, String> {
+    if !data.starts_with(b"BM") {
+        return Err("not a bmp".to_string());
+    }
+    let offset = le32(data, 10)? as usize;
+    let header_size = le32(data, 14)? as usize;
+
+    let (width, height, bpp, compression, colours_used) = if header_size == 12 {
+        // os/2 core header
+        (
+            le16(data, 18)? as i64,
+            le16(data, 20)? as i16 as i64,
+            le16(data, 24)? as usize,
+            BI_RGB,
+            0,
+        )
+    } else if header_size >= 40 {
+        (
+            le32(data, 18)? as i32 as i64,
+            le32(data, 22)? as i32 as i64,
+            le16(data, 28)? as usize,
+            le32(data, 30)?,
+            le32(data, 46)? as usize,
+        )
+    } else {
+        return Err(format!("unknown bmp header size {}", header_size));
+    };
+
+    // positive heights are stored bottom up
+    let bottom_up = height > 0;
+    let width = width.unsigned_abs() as usize;
+    let height = height.unsigned_abs() as usize;
+    let count = image::checked_size(width, height)?;
+    if !matches!(bpp, 1 | 2 | 4 | 8 | 16 | 24 | 32) {
+        return Err(format!("unsupported bmp bit depth {}", bpp));
+    }
+
+    // channel masks, after a plain info header or inside the larger ones
+    let mut masks: [u32; 4] = match bpp {
+        16 => [0x7c00, 0x03e0, 0x001f, 0],
+        _ => [0x00ff0000, 0x0000ff00, 0x000000ff, 0],
+    };
+    let mut palette_pos = 14 + header_size;
+    if compression == BI_BITFIELDS || compression == BI_ALPHABITFIELDS {
+        let count = if compression == BI_ALPHABITFIELDS || header_size >= 56 {
+            4
+        } else {
+            3
+        };
+        let at = if header_size == 40 { palette_pos } else { 54 };
+        for (i, m) in masks.iter_mut().enumerate().take(count) {
+            *m = le32(data, at + 4 * i)?;
+        }
+        if header_size == 40 {
+            palette_pos += 4 * count;
+        }
+    } else if compression != BI_RGB && compression != BI_RLE8 && compression != BI_RLE4 {
+        return Err(format!("unsupported bmp compression {}", compression));
+    }
+
+    let mut palette: Vec<Vec4> = Vec::new();
+    if bpp <= 8 {
+        let entry = if header_size == 12 { 3 } else { 4 };
+        let count = if colours_used > 0 {
+            colours_used.min(256)
+        } else {
+            1 << bpp
+        };
+        let p = data
+            .get(palette_pos..palette_pos + count * entry)
+            .ok_or("truncated palette")?;
+        palette = p
+            .chunks(entry)
+            .map(|c| {
+                Vec4::new(
+                    c[2] as f64 / 255.,
+                    c[1] as f64 / 255.,
+                    c[0] as f64 / 255.,
+                    1.,
+                )
+            })
+            .collect();
+    }
+
+    let pixel_data = data.get(offset..).ok_or("truncated image data")?;
+    let lookup = |i: usize| -> Vec4 {
+        return palette.get(i).copied().unwrap_or(Vec4::new(0., 0., 0., 1.));
+    };
+
+    // rows in file order, bottom up or not
+    let rows: Vec<Vec4> = if compression == BI_RLE8 || compression == BI_RLE4 {
+        let indices = decode_rle(pixel_data, width, height, compression == BI_RLE4)?;
+        indices.iter().map(|i| lookup(*i as usize)).collect()
+    } else {
+        let stride = (width * bpp).div_ceil(32) * 4;
+        if pixel_data.len() / stride < height {
+            return Err("truncated image data".to_string());
+        }
+        let mut rows: Vec<Vec4> = Vec::with_capacity(count);
+        for y in 0..height {
+            let row = pixel_data
+                .get(y * stride..(y + 1) * stride)
+                .ok_or("truncated image data")?;
+            for x in 0..width {
+                let px = match bpp {
+                    1 | 2 | 4 | 8 => {
+                        let bit = x * bpp;
+                        let shift = 8 - bpp - bit % 8;
+                        lookup(((row[bit / 8] >> shift) & ((1u16 << bpp) - 1) as u8) as usize)
+                    }
+                    16 => masked(
+                        u16::from_le_bytes([row[2 * x], row[2 * x + 1]]) as u32,
+                        &masks,
+                    ),
+                    24 => Vec4::new(
+                        row[3 * x + 2] as f64 / 255.,
+                        row[3 * x + 1] as f64 / 255.,
+                        row[3 * x] as f64 / 255.,
+                        1.,
+                    ),
+                    _ => masked(le32(row, 4 * x)?, &masks),
+                };
+                rows.push(px);
+            }
+        }
+        rows
+    };
+
+    let mut pixels: Vec<Vec4> = Vec::with_capacity(count);
+    for y in 0..height {
+        let src = if bottom_up { height - 1 - y } else { y };
+        pixels.extend(&rows[src * width..(src + 1) * width]);
+    }
+    return Ok(Image {
+        width: width,
+        height: height,
+        pixels: pixels,
+        alpha: masks[3] != 0,
+    });
+}
+
+// a channel for each mask, scaled by however many bits it has
+fn masked(v: u32, masks: &[u32; 4]) -> Vec4 {
+    let c = |m: u32, default: f64| -> f64 {
+        if m == 0 {
+            return default;
+        }
+        let max = (m >> m.trailing_zeros()) as f64;
+        return ((v & m) >> m.trailing_zeros()) as f64 / max;
+    };
+    return Vec4::new(
+        c(masks[0], 0.),
+        c(masks[1], 0.),
+        c(masks[2], 0.),
+        c(masks[3], 1.),
+    );
+}
+
+// palette indices of an rle8/rle4 bitmap, in file row order. skipped
+// pixels are left at index 0. the buffer grows with what's decoded rather
+// than trusting the header's size up front
+fn decode_rle(data: &[u8], width: usize, height: usize, four: bool) -> Result<Vec<u8>, String> {
+    let mut out: Vec<u8> = Vec::new();
+    let (mut x, mut y) = (0, 0);
+    let mut pos = 0;
+    let put = |out: &mut Vec<u8>, x: usize, y: usize, v: u8| {
+        if x < width && y < height {
+            let i = y * width + x;
+            if i >= out.len() {
+                out.resize(i + 1, 0);
+            }
+            out[i] = v;
+        }
+    };
+    let byte = |pos: usize| data.get(pos).copied().ok_or("truncated rle data");
+
+    loop {
+        let (count, value) = (byte(pos)? as usize, byte(pos + 1)?);
+        pos += 2;
+        if count > 0 {
+            // a run, rle4 alternates the two nibbles
+            for k in 0..count {
+                let v = if !four {
+                    value
+                } else if k % 2 == 0 {
+                    value >> 4
+                } else {
+                    value & 15
+                };
+                put(&mut out, x, y, v);
+                x += 1;
+            }
+            continue;
+        }
+        match value {
+            0 => {
+                x = 0;
+                y += 1;
+            }
+            1 => break,
+            2 => {
+                x += byte(pos)? as usize;
+                y += byte(pos + 1)? as usize;
+                pos += 2;
+            }
+            n => {
+                // literal pixels, padded to a whole number of 16 bit words
+                let n = n as usize;
+                let bytes = if four { n.div_ceil(2) } else { n };
+                for k in 0..n {
+                    let v = if !four {
+                        byte(pos + k)?
+                    } else if k % 2 == 0 {
+                        byte(pos + k / 2)? >> 4
+                    } else {
+                        byte(pos + k / 2)? & 15
+                    };
+                    put(&mut out, x, y, v);
+                    x += 1;
+                }
+                pos += (bytes + 1) & !1;
+            }
+        }
+        if y >= height {
+            break;
+        }
+    }
+    out.resize(width * height, 0);
+    return Ok(out);
+}
+
+fn le16(data: &[u8], pos: usize) -> Result<u16, String> {
+    let b = data.get(pos..pos + 2).ok_or("truncated header")?;
+    return Ok(u16::from_le_bytes([b[0], b[1]]));
+}
+
+fn le32(data: &[u8], pos: usize) -> Result<u32, String> {
+    let b = data.get(pos..pos + 4).ok_or("truncated header")?;
+    return Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgba(p: Vec4) -> [f64; 4] {
+        return [p.x, p.y, p.z, p.w];
+    }
+
+    // file and info headers, then the palette and the pixel data
+    fn encode(
+        width: i32,
+        height: i32,
+        bpp: u16,
+        compression: u32,
+        palette: &[u8],
+        pixels: &[u8],
+    ) -> Vec<u8> {
+        let offset = 54 + palette.len() as u32;
+        let mut out: Vec<u8> = b"BM".to_vec();
+        out.extend((offset + pixels.len() as u32).to_le_bytes());
+        out.extend([0; 4]);
+        out.extend(offset.to_le_bytes());
+        out.extend(40u32.to_le_bytes());
+        out.extend(width.to_le_bytes());
+        out.extend(height.to_le_bytes());
+        out.extend(1u16.to_le_bytes());
+        out.extend(bpp.to_le_bytes());
+        out.extend(compression.to_le_bytes());
+        out.extend([0; 12]);
+        out.extend(((palette.len() / 4) as u32).to_le_bytes());
+        out.extend([0; 4]);
+        out.extend(palette);
+        out.extend(pixels);
+        return out;
+    }
+
+    #[test]
+    fn reads_bottom_up_24_bit() {
+        // rows padded to 4 bytes, the bottom one first
+        let pixels = [0, 0, 255, 0, 255, 0, 0, 0, 255, 0, 0, 255, 255, 255, 0, 0];
+        let image = read_bmp(&encode(2, 2, 24, BI_RGB, &[], &pixels)).unwrap();
+        assert_eq!((image.width, image.height, image.alpha), (2, 2, false));
+        assert_eq!(rgba(image.pixels[0]), [0., 0., 1., 1.]);
+        assert_eq!(rgba(image.pixels[1]), [1., 1., 1., 1.]);
+        assert_eq!(rgba(image.pixels[2]), [1., 0., 0., 1.]);
+        assert_eq!(rgba(image.pixels[3]), [0., 1., 0., 1.]);
+    }
+
+    #[test]
+    fn reads_rle8() {
+        let palette = [0, 0, 0, 0, 255, 255, 255, 0];
+        // a run of three, end of line, a literal of three, end of bitmap
+        let pixels = [3, 1, 0, 0, 0, 3, 1, 0, 1, 0, 0, 1];
+        let image = read_bmp(&encode(3, -2, 8, BI_RLE8, &palette, &pixels)).unwrap();
+        let grey: Vec<f64> = image.pixels.iter().map(|p| p.x).collect();
+        assert_eq!(grey, vec![1., 1., 1., 1., 0., 1.]);
+    }
+
+    #[test]
+    fn rejects_huge_dimensions() {
+        let data = encode(0x7fffffff, 0x7fffffff, 24, BI_RGB, &[], &[]);
+        assert_eq!(data.len(), 54);
+        assert!(read_bmp(&data).is_err());
+        assert!(read_bmp(&encode(i32::MIN, 1, 24, BI_RGB, &[], &[])).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        assert!(read_bmp(&encode(100, 100, 24, BI_RGB, &[], &[0; 10])).is_err());
+        assert!(read_bmp(&encode(2, 2, 0, BI_RGB, &[], &[0; 16])).is_err());
+        assert!(read_bmp(&encode(4, 4, 8, BI_RLE8, &[0; 8], &[2, 1])).is_err());
+        assert!(read_bmp(&encode(2, 2, 24, BI_RGB, &[], &[0; 16])[..30]).is_err());
+    }
+}
@@ -0,0 +1,381 @@
+use crate::material::Material;
+use crate::microfacet::{
+    conductor_ior, fresnel_conductor, fresnel_dielectric, reflect, refract, schlick, Ggx,
+};
+use crate::sampling::cosine_hemisphere;
+use crate::vec4::Vec4;
+
+use std::f64::consts::PI;
+
+// all directions here are in the local shading frame (see sampling::Frame)
+// where the normal is +z, wo points away from the surface towards the viewer
+
+pub struct BsdfSample {
+    pub wi: Vec4,
+    pub weight: Vec4,   // f * |cos| / pdf
+    pub pdf: f64,       // solid angle pdf, meaningless for specular samples
+    pub specular: bool, // delta lobe, can't be hit by light sampling
+}
+
+// below this roughness glass and mirrors are treated as perfectly smooth
+const SMOOTH_ROUGHNESS: f64 = 0.03;
+
+// blender's Ks is its "specular" slider where 0.5 means the usual 4%
+// reflectance of dielectrics at normal incidence
+const KS_TO_F0: f64 = 0.08;
+
+pub enum Bsdf {
+    Lambertian { kd: Vec4 },
+    // cook-torrance: ggx specular lobe over a lambertian base
+    Microfacet { diffuse: Vec4, f0: Vec4, ggx: Ggx },
+    // glass, eta is the relative ior across the surface as seen from wo
+    SmoothDielectric { eta: f64 },
+    RoughDielectric { eta: f64, ggx: Ggx },
+    // mirrors and metals, r is the reflectance at normal incidence which
+    // rises towards white at grazing angles when fresnel is on
+    SmoothConductor { r: Vec4, fresnel: bool },
+    RoughConductor { r: Vec4, fresnel: bool, ggx: Ggx },
+}
+
+fn zero() -> Vec4 {
+    return Vec4::new(0., 0., 0., 0.);
+}
+
+impl Bsdf {
+    // entering is whether wo is on the outside of the surface, only matters
+    // for refractive materials
+    pub fn from_material(mat: &Material, entering: bool) -> Bsdf {
+        // illum 0 and 1 are diffuse only, 3/5 are ray traced reflections
+        // (5 with fresnel), 4/6/7/9 are the mtl models with refraction and
+        // everything else gets a specular lobe
+        match mat.illum {
+            0 | 1 => Bsdf::Lambertian { kd: mat.kd },
+            3 | 5 => {
+                let m = mat.metallic.clamp(0., 1.);
+                let r: Vec4 = mat.ks * (1. - m) + mat.kd * m;
+                let fresnel = mat.illum == 5;
+                let roughness = mat.roughness();
+                if roughness < SMOOTH_ROUGHNESS {
+                    Bsdf::SmoothConductor {
+                        r: r,
+                        fresnel: fresnel,
+                    }
+                } else {
+                    Bsdf::RoughConductor {
+                        r: r,
+                        fresnel: fresnel,
+                        ggx: Ggx::new(roughness),
+                    }
+                }
+            }
+            4 | 6 | 7 | 9 => {
+                let ior = mat.ior();
+                let eta = if entering { ior } else { 1. / ior };
+                let roughness = mat.roughness();
+                if roughness < SMOOTH_ROUGHNESS {
+                    Bsdf::SmoothDielectric { eta: eta }
+                } else {
+                    Bsdf::RoughDielectric {
+                        eta: eta,
+                        ggx: Ggx::new(roughness),
+                    }
+                }
+            }
+            _ => {
+                let m = mat.metallic.clamp(0., 1.);
+                let dielectric_f0: Vec4 = mat.ks * KS_TO_F0;
+                Bsdf::Microfacet {
+                    diffuse: mat.kd * (1. - m),
+                    f0: dielectric_f0 * (1. - m) + mat.kd * m,
+                    ggx: Ggx::new(mat.roughness()),
+                }
+            }
+        }
+    }
+
+    fn specular_probability(diffuse: &Vec4, f0: &Vec4, wo: &Vec4) -> f64 {
+        // pick lobes roughly by how much energy they reflect
+        let fs = schlick(*f0, wo.z).luminance();
+        let fd = diffuse.luminance();
+        if fd <= 0. {
+            return 1.;
+        }
+        return (fs / (fs + fd)).clamp(0.1, 0.9);
+    }
+
+    fn conductor_reflectance(r: &Vec4, fresnel: bool, cos: f64) -> Vec4 {
+        if !fresnel {
+            return *r;
+        }
+        let f = |r: f64| {
+            let (eta, k) = conductor_ior(r);
+            fresnel_conductor(cos, eta, k)
+        };
+        return Vec4::new(f(r.x), f(r.y), f(r.z), 0.);
+    }
+
+    pub fn is_transmissive(&self) -> bool {
+        return matches!(
+            self,
+            Bsdf::SmoothDielectric { .. } | Bsdf::RoughDielectric { .. }
+        );
+    }
+
+    // relative ior across the surface as seen from wo, 1 for anything light
+    // can't pass through
+    pub fn eta(&self) -> f64 {
+        match self {
+            Bsdf::SmoothDielectric { eta } | Bsdf::RoughDielectric { eta, .. } => *eta,
+            _ => 1.,
+        }
+    }
+
+    fn rough_dielectric_half(wo: &Vec4, wi: &Vec4, eta: f64) -> Option<(Vec4, bool)> {
+        // generalized half vector, flipped to the +z side, and whether this is
+        // a reflection. None for configurations the microfacets can't produce
+        let reflection = wi.z > 0.;
+        let etap = if reflection { 1. } else { eta };
+        let mut h: Vec4 = *wi * etap + *wo;
+        if h.dot(h) <= 0. {
+            return None;
+        }
+        h = h.normalize();
+        if h.z < 0. {
+            h *= -1.;
+        }
+        // backfacing microfacets
+        if h.dot(*wi) * wi.z < 0. || h.dot(*wo) * wo.z < 0. {
+            return None;
+        }
+        return Some((h, reflection));
+    }
+
+    pub fn eval(&self, wo: &Vec4, wi: &Vec4) -> Vec4 {
+        match self {
+            Bsdf::Lambertian { kd } => {
+                if wo.z <= 0. || wi.z <= 0. {
+                    return zero();
+                }
+                return *kd / PI;
+            }
+            Bsdf::Microfacet { diffuse, f0, ggx } => {
+                if wo.z <= 0. || wi.z <= 0. {
+                    return zero();
+                }
+                let h: Vec4 = (*wo + *wi).normalize();
+                let f: Vec4 = schlick(*f0, wi.dot(h));
+                let spec = ggx.d(&h) * ggx.g(wo, wi) / (4. * wo.z * wi.z);
+                return *diffuse / PI + f * spec;
+            }
+            Bsdf::SmoothDielectric { .. } => zero(),
+            Bsdf::RoughDielectric { eta, ggx } => {
+                // https://www.graphics.cornell.edu/~bjw/microfacetbsdf.pdf
+                if wo.z <= 0. || wi.z == 0. {
+                    return zero();
+                }
+                let (h, reflection) = match Bsdf::rough_dielectric_half(wo, wi, *eta) {
+                    Some(x) => x,
+                    None => return zero(),
+                };
+                let f = fresnel_dielectric(wo.dot(h), *eta);
+                let dg = ggx.d(&h) * ggx.g(wo, wi);
+                if reflection {
+                    let r = dg * f / (4. * wo.z * wi.z);
+                    return Vec4::new(r, r, r, 0.);
+                }
+
+                let denom = wi.dot(h) + wo.dot(h) / eta;
+                let t =
+                    dg * (1. - f) * (wi.dot(h) * wo.dot(h) / (wo.z * wi.z * denom * denom)).abs();
+                // radiance is compressed going into the denser medium
+                let t = t / (eta * eta);
+                return Vec4::new(t, t, t, 0.);
+            }
+            Bsdf::SmoothConductor { .. } => zero(),
+            Bsdf::RoughConductor { r, fresnel, ggx } => {
+                if wo.z <= 0. || wi.z <= 0. {
+                    return zero();
+                }
+                let h: Vec4 = (*wo + *wi).normalize();
+                let f: Vec4 = Bsdf::conductor_reflectance(r, *fresnel, wo.dot(h));
+                return f * (ggx.d(&h) * ggx.g(wo, wi) / (4. * wo.z * wi.z));
+            }
+        }
+    }
+
+    pub fn pdf(&self, wo: &Vec4, wi: &Vec4) -> f64 {
+        match self {
+            Bsdf::Lambertian { .. } => {
+                if wo.z <= 0. || wi.z <= 0. {
+                    return 0.;
+                }
+                return wi.z / PI;
+            }
+            Bsdf::Microfacet { diffuse, f0, ggx } => {
+                if wo.z <= 0. || wi.z <= 0. {
+                    return 0.;
+                }
+                let ps = Bsdf::specular_probability(diffuse, f0, wo);
+                let h: Vec4 = (*wo + *wi).normalize();
+                let spec_pdf = ggx.pdf_h(wo, &h) / (4. * wo.dot(h));
+                return ps * spec_pdf + (1. - ps) * wi.z / PI;
+            }
+            Bsdf::SmoothDielectric { .. } => 0.,
+            Bsdf::RoughDielectric { eta, ggx } => {
+                if wo.z <= 0. || wi.z == 0. {
+                    return 0.;
+                }
+                let (h, reflection) = match Bsdf::rough_dielectric_half(wo, wi, *eta) {
+                    Some(x) => x,
+                    None => return 0.,
+                };
+                let r = fresnel_dielectric(wo.dot(h), *eta);
+                if reflection {
+                    return ggx.pdf_h(wo, &h) / (4. * wo.dot(h).abs()) * r;
+                }
+                let denom = wi.dot(h) + wo.dot(h) / eta;
+                let dh_dwi = wi.dot(h).abs() / (denom * denom);
+                return ggx.pdf_h(wo, &h) * dh_dwi * (1. - r);
+            }
+            Bsdf::SmoothConductor { .. } => 0.,
+            Bsdf::RoughConductor { ggx, .. } => {
+                if wo.z <= 0. || wi.z <= 0. {
+                    return 0.;
+                }
+                let h: Vec4 = (*wo + *wi).normalize();
+                return ggx.pdf_h(wo, &h) / (4. * wo.dot(h));
+            }
+        }
+    }
+
+    pub fn sample(&self, wo: &Vec4, u1: f64, u2: f64, uc: f64) -> Option<BsdfSample> {
+        match self {
+            Bsdf::Lambertian { kd } => {
+                if wo.z <= 0. {
+                    return None;
+                }
+                // the cos/pi terms of the brdf and the pdf cancel out
+                let wi: Vec4 = cosine_hemisphere(u1, u2);
+                if wi.z <= 0. {
+                    return None;
+                }
+                return Some(BsdfSample {
+                    wi: wi,
+                    weight: *kd,
+                    pdf: wi.z / PI,
+                    specular: false,
+                });
+            }
+            Bsdf::Microfacet { diffuse, f0, ggx } => {
+                if wo.z <= 0. {
+                    return None;
+                }
+                let wi: Vec4 = if uc < Bsdf::specular_probability(diffuse, f0, wo) {
+                    reflect(wo, &ggx.sample_h(wo, u1, u2))
+                } else {
+                    cosine_hemisphere(u1, u2)
+                };
+                if wi.z <= 0. {
+                    return None;
+                }
+
+                let pdf = self.pdf(wo, &wi);
+                if pdf <= 0. {
+                    return None;
+                }
+                return Some(BsdfSample {
+                    wi: wi,
+                    weight: self.eval(wo, &wi) * (wi.z / pdf),
+                    pdf: pdf,
+                    specular: false,
+                });
+            }
+            Bsdf::SmoothDielectric { eta } => {
+                if wo.z <= 0. {
+                    return None;
+                }
+                // pick reflection or refraction by the fresnel term, which
+                // then cancels out of the weight
+                let f = fresnel_dielectric(wo.z, *eta);
+                if uc < f {
+                    return Some(BsdfSample {
+                        wi: Vec4::new(-wo.x, -wo.y, wo.z, 0.),
+                        weight: Vec4::new(1., 1., 1., 0.),
+                        pdf: f,
+                        specular: true,
+                    });
+                }
+                let n: Vec4 = Vec4::new(0., 0., 1., 0.);
+                let wi: Vec4 = refract(wo, &n, *eta)?;
+                let t = 1. / (eta * eta);
+                return Some(BsdfSample {
+                    wi: wi,
+                    weight: Vec4::new(t, t, t, 0.),
+                    pdf: 1. - f,
+                    specular: true,
+                });
+            }
+            Bsdf::RoughDielectric { eta, ggx } => {
+                if wo.z <= 0. {
+                    return None;
+                }
+                let h: Vec4 = ggx.sample_h(wo, u1, u2);
+                let f = fresnel_dielectric(wo.dot(h), *eta);
+                let wi: Vec4 = if uc < f {
+                    let wi = reflect(wo, &h);
+                    if wi.z <= 0. {
+                        return None;
+                    }
+                    wi
+                } else {
+                    let wi = refract(wo, &h, *eta)?;
+                    if wi.z >= 0. {
+                        return None;
+                    }
+                    wi
+                };
+
+                let pdf = self.pdf(wo, &wi);
+                if pdf <= 0. {
+                    return None;
+                }
+                return Some(BsdfSample {
+                    wi: wi,
+                    weight: self.eval(wo, &wi) * (wi.z.abs() / pdf),
+                    pdf: pdf,
+                    specular: false,
+                });
+            }
+            Bsdf::SmoothConductor { r, fresnel } => {
+                if wo.z <= 0. {
+                    return None;
+                }
+                return Some(BsdfSample {
+                    wi: Vec4::new(-wo.x, -wo.y, wo.z, 0.),
+                    weight: Bsdf::conductor_reflectance(r, *fresnel, wo.z),
+                    pdf: 1.,
+                    specular: true,
+                });
+            }
+            Bsdf::RoughConductor { ggx, .. } => {
+                if wo.z <= 0. {
+                    return None;
+                }
+                let wi: Vec4 = reflect(wo, &ggx.sample_h(wo, u1, u2));
+                if wi.z <= 0. {
+                    return None;
+                }
+                let pdf = self.pdf(wo, &wi);
+                if pdf <= 0. {
+                    return None;
+                }
+                return Some(BsdfSample {
+                    wi: wi,
+                    weight: self.eval(wo, &wi) * (wi.z / pdf),
+                    pdf: pdf,
+                    specular: false,
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,327 @@
+use crate::aabb::AABB;
+use crate::accel::Accel;
+use crate::tracer::Ray;
+use crate::triangle::Triangle;
+use crate::vec4::Vec4;
+
+// same relative costs as the kd-tree so the two stay comparable
+const TRAVERSAL_COST: f64 = 1.;
+const INTERSECT_COST: f64 = 1.5;
+const NUM_BINS: usize = 16;
+// leaves smaller than this are kept even when sah would like to split more
+const MIN_LEAF: usize = 1;
+// and larger ones are split even when sah would rather not
+const MAX_LEAF: usize = 8;
+// deep enough for any sane scene, also bounds the traversal stack
+const MAX_DEPTH: usize = 60;
+const STACK_SIZE: usize = MAX_DEPTH + 4;
+
+// interior nodes have count 0 and their children at first and first + 1,
+// leaves cover indices[first..first + count]
+struct BVHNode {
+    aabb: AABB,
+    first: usize,
+    count: usize,
+}
+
+// bounding volume hierarchy built with binned sah, see wald, "on fast
+// construction of sah-based bounding volume hierarchies"
+// https://www.sci.utah.edu/~wald/Publications/2007/ParallelBVHBuild/fastbuild.pdf
+// every triangle is referenced exactly once, unlike in the kd-tree
+pub struct BVH {
+    nodes: Vec<BVHNode>,
+    indices: Vec<usize>, // into Obj::triangles, leaves point at ranges of this
+}
+
+#[derive(Copy, Clone)]
+struct Bin {
+    aabb: AABB,
+    count: usize,
+}
+
+impl BVH {
+    pub fn new(triangles: &[Triangle]) -> BVH {
+        let bounds: Vec<AABB> = triangles.iter().map(|t| t.aabb()).collect();
+        let centroids: Vec<Vec4> = bounds.iter().map(|b| b.centroid()).collect();
+
+        let mut bvh = BVH {
+            nodes: Vec::with_capacity(2 * triangles.len()),
+            indices: (0..triangles.len()).collect(),
+        };
+        bvh.nodes.push(BVHNode {
+            aabb: AABB::empty(),
+            first: 0,
+            count: 0,
+        });
+        bvh.build(0, 0, triangles.len(), 0, &bounds, &centroids);
+        return bvh;
+    }
+
+    // fills in node for the triangles in indices[start..end]
+    fn build(
+        &mut self,
+        node: usize,
+        start: usize,
+        end: usize,
+        depth: usize,
+        bounds: &[AABB],
+        centroids: &[Vec4],
+    ) {
+        let mut aabb = AABB::empty();
+        let mut centroid_bb = AABB::empty();
+        for i in &self.indices[start..end] {
+            aabb = aabb.union(&bounds[*i]);
+            centroid_bb.grow(&centroids[*i]);
+        }
+        self.nodes[node].aabb = aabb;
+
+        let n = end - start;
+        let split = if depth < MAX_DEPTH {
+            self.find_split(start, end, &aabb, &centroid_bb, bounds, centroids)
+        } else {
+            None
+        };
+        let mid = match split {
+            Some(mid) => mid,
+            None => {
+                self.nodes[node].first = start;
+                self.nodes[node].count = n;
+                return;
+            }
+        };
+        debug_assert!(mid > start && mid < end && n > MIN_LEAF);
+
+        let left = self.nodes.len();
+        for _ in 0..2 {
+            self.nodes.push(BVHNode {
+                aabb: AABB::empty(),
+                first: 0,
+                count: 0,
+            });
+        }
+        self.nodes[node].first = left;
+        self.build(left, start, mid, depth + 1, bounds, centroids);
+        self.build(left + 1, mid, end, depth + 1, bounds, centroids);
+    }
+
+    // bins the centroids along every axis and partitions indices at the
+    // cheapest bin boundary, None if the node should stay a leaf
+    fn find_split(
+        &mut self,
+        start: usize,
+        end: usize,
+        aabb: &AABB,
+        centroid_bb: &AABB,
+        bounds: &[AABB],
+        centroids: &[Vec4],
+    ) -> Option<usize> {
+        let n = end - start;
+        if n <= MIN_LEAF {
+            return None;
+        }
+
+        let area = aabb.surface_area();
+        let mut best: Option<(f64, usize, usize)> = None; // cost, axis, bin
+        for axis in 0..3 {
+            let lo = centroid_bb.min.elem(axis);
+            let extent = centroid_bb.max.elem(axis) - lo;
+            if extent <= 0. {
+                continue;
+            }
+            let scale = NUM_BINS as f64 / extent;
+
+            let mut bins = [Bin {
+                aabb: AABB::empty(),
+                count: 0,
+            }; NUM_BINS];
+            for i in &self.indices[start..end] {
+                let b = bin_index(centroids[*i].elem(axis), lo, scale);
+                bins[b].count += 1;
+                bins[b].aabb = bins[b].aabb.union(&bounds[*i]);
+            }
+
+            // sweep from the right to get the cost of everything past each
+            // boundary, then from the left evaluating every split
+            let mut right_area = [0.; NUM_BINS];
+            let mut right_count = [0; NUM_BINS];
+            let mut bb = AABB::empty();
+            let mut count = 0;
+            for b in (1..NUM_BINS).rev() {
+                bb = bb.union(&bins[b].aabb);
+                count += bins[b].count;
+                right_area[b] = if count > 0 { bb.surface_area() } else { 0. };
+                right_count[b] = count;
+            }
+
+            let mut bb = AABB::empty();
+            let mut count = 0;
+            for b in 0..NUM_BINS - 1 {
+                bb = bb.union(&bins[b].aabb);
+                count += bins[b].count;
+                if count == 0 || right_count[b + 1] == 0 {
+                    continue;
+                }
+                let cost = TRAVERSAL_COST
+                    + INTERSECT_COST
+                        * (bb.surface_area() * count as f64
+                            + right_area[b + 1] * right_count[b + 1] as f64)
+                        / area;
+                if best.is_none_or(|(c, _, _)| cost < c) {
+                    best = Some((cost, axis, b));
+                }
+            }
+        }
+
+        let (cost, axis, bin) = best?;
+        if cost >= INTERSECT_COST * n as f64 && n <= MAX_LEAF {
+            return None;
+        }
+
+        let lo = centroid_bb.min.elem(axis);
+        let scale = NUM_BINS as f64 / (centroid_bb.max.elem(axis) - lo);
+        let mut mid = start;
+        for i in start..end {
+            let c = centroids[self.indices[i]].elem(axis);
+            if bin_index(c, lo, scale) <= bin {
+                self.indices.swap(i, mid);
+                mid += 1;
+            }
+        }
+        return Some(mid);
+    }
+
+    fn traverse(
+        &self,
+        triangles: &[Triangle],
+        r: &Ray,
+        tmax: f64,
+        any: bool,
+    ) -> Option<(usize, f64)> {
+        // an empty scene leaves the root with count 0, which would otherwise
+        // read as an interior node pointing back at itself
+        if self.indices.is_empty() {
+            return None;
+        }
+        let inv_dir = Vec4::new(1. / r.dir.x, 1. / r.dir.y, 1. / r.dir.z, 0.);
+        let mut best: (Option<usize>, f64) = (None, tmax);
+
+        let mut stack = [0usize; STACK_SIZE];
+        let mut top = 0;
+        if hit_box(&self.nodes[0].aabb, r, &inv_dir, tmax).is_some() {
+            stack[0] = 0;
+            top = 1;
+        }
+
+        while top > 0 {
+            top -= 1;
+            let node = &self.nodes[stack[top]];
+
+            if node.count > 0 {
+                for i in &self.indices[node.first..node.first + node.count] {
+                    if let Some(t) = triangles[*i].intersect(r) {
+                        if t < best.1 {
+                            best = (Some(*i), t);
+                            if any {
+                                return best.0.map(|i| (i, best.1));
+                            }
+                        }
+                    }
+                }
+                continue;
+            }
+
+            // visit the nearer child first so the far one can often be
+            // skipped once something closer was hit
+            let (a, b) = (node.first, node.first + 1);
+            let ta = hit_box(&self.nodes[a].aabb, r, &inv_dir, best.1);
+            let tb = hit_box(&self.nodes[b].aabb, r, &inv_dir, best.1);
+            match (ta, tb) {
+                (Some(ta), Some(tb)) => {
+                    let (near, far) = if ta <= tb { (a, b) } else { (b, a) };
+                    stack[top] = far;
+                    stack[top + 1] = near;
+                    top += 2;
+                }
+                (Some(_), None) => {
+                    stack[top] = a;
+                    top += 1;
+                }
+                (None, Some(_)) => {
+                    stack[top] = b;
+                    top += 1;
+                }
+                (None, None) => {}
+            }
+        }
+        return best.0.map(|i| (i, best.1));
+    }
+
+    // (nodes, leaves, depth) for the build summary
+    fn stats(&self, node: usize) -> (usize, usize, usize) {
+        let n = &self.nodes[node];
+        if n.count > 0 || self.indices.is_empty() {
+            return (1, 1, 0);
+        }
+        let l = self.stats(n.first);
+        let r = self.stats(n.first + 1);
+        return (l.0 + r.0 + 1, l.1 + r.1, usize::max(l.2, r.2) + 1);
+    }
+}
+
+impl Accel for BVH {
+    fn closest_hit(&self, triangles: &[Triangle], r: &Ray, tmax: f64) -> Option<(usize, f64)> {
+        return self.traverse(triangles, r, tmax, false);
+    }
+
+    fn any_hit(&self, triangles: &[Triangle], r: &Ray, tmax: f64) -> bool {
+        return self.traverse(triangles, r, tmax, true).is_some();
+    }
+
+    fn summary(&self) -> String {
+        let (nodes, leaves, depth) = self.stats(0);
+        return format!(
+            "bvh: {} nodes, {} leaves, depth {}, {:.1} triangles per leaf",
+            nodes,
+            leaves,
+            depth,
+            self.indices.len() as f64 / leaves as f64
+        );
+    }
+}
+
+fn bin_index(c: f64, lo: f64, scale: f64) -> usize {
+    return usize::min(((c - lo) * scale) as usize, NUM_BINS - 1);
+}
+
+// entry distance of r into the box if it gets there before tmax
+fn hit_box(aabb: &AABB, r: &Ray, inv_dir: &Vec4, tmax: f64) -> Option<f64> {
+    let mut t0: f64 = 0.;
+    let mut t1: f64 = tmax;
+    for d in 0..3 {
+        let ta = (aabb.min.elem(d) - r.origin.elem(d)) * inv_dir.elem(d);
+        let tb = (aabb.max.elem(d) - r.origin.elem(d)) * inv_dir.elem(d);
+        t0 = f64::max(t0, f64::min(ta, tb));
+        t1 = f64::min(t1, f64::max(ta, tb));
+    }
+    if t0 <= t1 {
+        return Some(t0);
+    }
+    return None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_scene() {
+        let bvh = BVH::new(&[]);
+        let r = Ray {
+            origin: Vec4::new(0., 0., 0., 1.),
+            dir: Vec4::new(0., 0., -1., 0.),
+        };
+        assert!(bvh.closest_hit(&[], &r, f64::INFINITY).is_none());
+        assert!(!bvh.any_hit(&[], &r, f64::INFINITY));
+        assert!(bvh.summary().starts_with("bvh: 1 nodes"));
+    }
+}
@@ -0,0 +1,101 @@
+use crate::mat4::Mat4;
+use crate::sampling::concentric_disk;
+use crate::tracer::Ray;
+use crate::vec4::Vec4;
+
+// thin lens camera looking from eye towards target, an aperture of 0 is a
+// pinhole with everything in focus
+pub struct Camera {
+    pub eye: Vec4,
+    pub aspect: f64,     // width / height
+    pub aperture: f64,   // lens radius
+    pub focus_dist: f64, // distance to the plane in focus
+    to_world: Mat4,
+    scale: f64, // half height of the image plane at distance 1
+}
+
+impl Camera {
+    // focus_dist defaults to the distance to the target. fails when the
+    // view can't be oriented or the lens is nonsense, the basis or the rays
+    // would come out as nans otherwise
+    pub fn new(
+        eye: Vec4,
+        target: Vec4,
+        up: Vec4,
+        fov: f64,
+        aspect: f64,
+        aperture: f64,
+        focus_dist: Option<f64>,
+    ) -> Result<Camera, String> {
+        let finite = |v: &Vec4| v.x.is_finite() && v.y.is_finite() && v.z.is_finite();
+        if !finite(&eye) || !finite(&target) || !finite(&up) {
+            return Err("camera position, target and up must be finite".to_string());
+        }
+        let view: Vec4 = target - eye;
+        if view.length() < 1e-9 {
+            return Err("camera target is the same point as the eye".to_string());
+        }
+        if up.length() < 1e-9 {
+            return Err("camera up vector is zero".to_string());
+        }
+        // looking straight along up leaves the roll undefined
+        let dir: Vec4 = view.normalize();
+        let up_v: Vec4 = up.normalize();
+        if dir.cross(up_v).length() < 1e-6 {
+            return Err("camera up vector is parallel to the view direction".to_string());
+        }
+        if !(fov > 0. && fov < 180.) {
+            return Err(format!(
+                "field of view must be between 0 and 180 degrees, got {}",
+                fov
+            ));
+        }
+        if !(aperture >= 0. && aperture.is_finite()) {
+            return Err(format!(
+                "aperture must be finite and not negative, got {}",
+                aperture
+            ));
+        }
+        if let Some(d) = focus_dist {
+            if !(d > 0. && d.is_finite()) {
+                return Err(format!("focus distance must be positive, got {}", d));
+            }
+        }
+
+        return Ok(Camera {
+            eye: eye,
+            aspect: aspect,
+            aperture: aperture,
+            focus_dist: focus_dist.unwrap_or((target - eye).length()),
+            to_world: Mat4::look_at(&eye, &target, &up_v),
+            scale: (fov.to_radians() * 0.5).tan(),
+        });
+    }
+
+    // u, v in [0, 1] across the image, starting at the top left corner.
+    // lens_u, lens_v pick the point on the lens and are ignored for pinholes
+    pub fn ray(&self, u: f64, v: f64, lens_u: f64, lens_v: f64) -> Ray {
+        let x = (u * 2. - 1.) * self.aspect * self.scale;
+        let y = -(v * 2. - 1.) * self.scale;
+
+        let d: Vec4 = Vec4::new(x, y, -1., 0.);
+
+        if self.aperture <= 0. {
+            return Ray {
+                origin: self.eye,
+                dir: self.to_world * d.normalize(),
+            };
+        }
+
+        // every ray through the lens converges on the same point of the
+        // focal plane, anything off that plane gets blurred
+        let (lx, ly) = concentric_disk(lens_u, lens_v);
+        let lens: Vec4 = Vec4::new(lx * self.aperture, ly * self.aperture, 0., 0.);
+        let focus: Vec4 = d * self.focus_dist;
+
+        return Ray {
+            origin: self.eye + self.to_world * lens,
+            dir: self.to_world * (focus - lens).normalize(),
+        };
+    }
+}
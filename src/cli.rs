@@ -0,0 +1,400 @@
+use crate::accel::AccelKind;
+use crate::exr::{ExrCompression, ExrPixelType};
+use crate::filter::FilterKind;
+use crate::mipmap::TextureFilter;
+use crate::sampler::SamplerKind;
+use crate::tonemap::{ToneMapOp, Transfer};
+use crate::tracer::{Integrator, MAX_DEPTH};
+use crate::vec4::Vec4;
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread;
+
+// std only command line parsing, every option takes exactly one value
+
+const MAX_FILTER_RADIUS: f64 = 16.;
+
+pub const USAGE: &str = "\
+usage: rustpt [options] [input.obj]
+
+options:
+  -i, --input <file>          scene to render (default: obj/cornell.obj)
+  -o, --output <file>         image to write (default: out.ppm)
+  -f, --format <fmt>          output format, guessed from the extension if
+                              missing: ppm, png, pfm, hdr or exr
+      --bit-depth <8|16>      bits per channel for png (default: 8)
+      --alpha <on|off>        write coverage as alpha for png and exr
+                              (default: off)
+      --tonemap <op>          tone mapping for ppm and png: clamp, reinhard,
+                              reinhard-extended, hable or aces (default:
+                              clamp)
+      --exposure <ev>         exposure adjustment in stops (default: 0)
+      --white <l>             luminance that maps to white for
+                              reinhard-extended (default: brightest pixel)
+      --transfer <t>          srgb or gamma (2.2) encoding (default: srgb)
+      --exr-compression <c>   none, rle or zip (default: zip)
+      --exr-type <t>          half or float (default: half)
+  -W, --width <px>            image width (default: 1000)
+  -H, --height <px>           image height (default: 1000)
+  -s, --spp <n>               samples per pixel (default: 64)
+      --sampler <name>        independent, stratified, halton or sobol
+                              (default: sobol)
+      --seed <n>              sampler seed, same seed gives the same image
+                              (default: 0)
+      --filter <name>         pixel reconstruction filter: box, tent,
+                              gaussian, mitchell or blackman-harris
+                              (default: gaussian)
+      --filter-radius <px>    filter radius in pixels (default: 0.5 box,
+                              1 tent, 1.5 gaussian, 2 mitchell and
+                              blackman-harris, at most 16)
+  -t, --threads <n>           render threads (default: available cores)
+  -d, --depth <n>             max path tracer bounces (default: 4)
+      --integrator <name>     path or blinn-phong (default: path)
+      --accel <name>          kdtree or bvh (default: kdtree)
+      --lenient <on|off>      skip malformed obj/mtl lines with a warning
+                              instead of stopping (default: off). lines
+                              that load but look off, like non-planar
+                              faces, are warned about either way
+      --search-path <dirs>    where to look for mtl files and textures not
+                              found next to the file naming them, separated
+                              like PATH, may be given more than once
+      --texture-filter <name> bilinear, trilinear or ewa, for texture maps
+                              without a -filter option (default: trilinear)
+      --eye <x,y,z>           camera position
+      --look-at <x,y,z>       point the camera looks at (default: straight
+                              down -z)
+      --up <x,y,z>            camera up vector (default: 0,1,0)
+      --fov <degrees>         vertical field of view (default: 53.13)
+      --aperture <r>          lens radius for depth of field (default: 0,
+                              pinhole)
+      --focus-dist <d>        distance to the plane in focus (default:
+                              distance to --look-at)
+  -h, --help                  print this message
+";
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum OutputFormat {
+    Ppm,
+    Png,
+    Pfm,
+    Hdr, // radiance rgbe
+    Exr,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OutputFormat, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "ppm" => Ok(OutputFormat::Ppm),
+            "png" => Ok(OutputFormat::Png),
+            "pfm" => Ok(OutputFormat::Pfm),
+            "hdr" | "rgbe" => Ok(OutputFormat::Hdr),
+            "exr" => Ok(OutputFormat::Exr),
+            _ => Err(format!("unknown output format: {}", s)),
+        }
+    }
+}
+
+impl OutputFormat {
+    pub fn from_path(path: &str) -> Option<OutputFormat> {
+        let ext = Path::new(path).extension()?.to_str()?;
+        return ext.parse().ok();
+    }
+}
+
+pub struct Options {
+    pub input: String,
+    pub output: String,
+    pub format: OutputFormat,
+    pub bit_depth: u8,
+    pub alpha: bool,
+    pub tonemap: ToneMapOp,
+    pub exposure: f64,
+    pub white: Option<f64>,
+    pub transfer: Transfer,
+    pub exr_compression: ExrCompression,
+    pub exr_type: ExrPixelType,
+    pub width: usize,
+    pub height: usize,
+    pub spp: usize,
+    pub sampler: SamplerKind,
+    pub seed: u64,
+    pub filter: FilterKind,
+    pub filter_radius: Option<f64>,
+    pub threads: usize,
+    pub max_depth: usize,
+    pub integrator: Integrator,
+    pub accel: AccelKind,
+    pub lenient: bool,
+    pub search_path: Vec<PathBuf>,
+    pub texture_filter: TextureFilter,
+    pub eye: Vec4,
+    pub look_at: Option<Vec4>,
+    pub up: Vec4,
+    pub fov: f64, // vertical, in degrees
+    pub aperture: f64,
+    pub focus_dist: Option<f64>,
+}
+
+pub enum CliError {
+    Help,
+    Invalid(String),
+}
+
+impl Options {
+    pub fn default() -> Options {
+        Options {
+            input: "obj/cornell.obj".to_string(),
+            output: "out.ppm".to_string(),
+            format: OutputFormat::Ppm,
+            bit_depth: 8,
+            alpha: false,
+            tonemap: ToneMapOp::Clamp,
+            exposure: 0.,
+            white: None,
+            transfer: Transfer::Srgb,
+            exr_compression: ExrCompression::Zip,
+            exr_type: ExrPixelType::Half,
+            width: 1000,
+            height: 1000,
+            spp: 64,
+            sampler: SamplerKind::Sobol,
+            seed: 0,
+            filter: FilterKind::Gaussian,
+            filter_radius: None,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            max_depth: MAX_DEPTH,
+            integrator: Integrator::PathTracer,
+            accel: AccelKind::KdTree,
+            lenient: false,
+            search_path: Vec::new(),
+            texture_filter: TextureFilter::Trilinear,
+            eye: Vec4::new(-0.2345, 2.58355, 5., 1.),
+            look_at: None,
+            up: Vec4::new(0., 1., 0., 0.),
+            fov: 53.13, // what the old hardcoded image plane at z = -2 gave
+            aperture: 0.,
+            focus_dist: None,
+        }
+    }
+
+    pub fn parse(args: &[String]) -> Result<Options, CliError> {
+        let mut o = Options::default();
+        let mut format: Option<OutputFormat> = None;
+        let mut input: Option<String> = None;
+
+        let mut it = args.iter();
+        while let Some(arg) = it.next() {
+            if arg == "-h" || arg == "--help" {
+                return Err(CliError::Help);
+            }
+            if !arg.starts_with('-') {
+                if input.is_some() {
+                    return Err(invalid(format!("unexpected argument: {}", arg)));
+                }
+                input = Some(arg.clone());
+                continue;
+            }
+
+            let value: &str = match it.next() {
+                Some(v) => v,
+                None => return Err(invalid(format!("missing value for {}", arg))),
+            };
+
+            match arg.as_str() {
+                "-i" | "--input" => input = Some(value.to_string()),
+                "-o" | "--output" => o.output = value.to_string(),
+                "-f" | "--format" => format = Some(value.parse().map_err(invalid)?),
+                "--bit-depth" => {
+                    o.bit_depth = parse_num(arg, value)?;
+                    if o.bit_depth != 8 && o.bit_depth != 16 {
+                        return Err(invalid(format!("{} must be 8 or 16", arg)));
+                    }
+                }
+                "--alpha" => o.alpha = parse_switch(arg, value)?,
+                "--tonemap" => o.tonemap = value.parse().map_err(invalid)?,
+                "--exposure" => o.exposure = parse_float(arg, value)?,
+                "--white" => {
+                    let w: f64 = parse_float(arg, value)?;
+                    if w <= 0. {
+                        return Err(invalid(format!("{} must be positive", arg)));
+                    }
+                    o.white = Some(w);
+                }
+                "--transfer" => o.transfer = value.parse().map_err(invalid)?,
+                "--exr-compression" => o.exr_compression = value.parse().map_err(invalid)?,
+                "--exr-type" => o.exr_type = value.parse().map_err(invalid)?,
+                "-W" | "--width" => o.width = parse_count(arg, value)?,
+                "-H" | "--height" => o.height = parse_count(arg, value)?,
+                "-s" | "--spp" => o.spp = parse_count(arg, value)?,
+                "--sampler" => o.sampler = value.parse().map_err(invalid)?,
+                "--seed" => o.seed = parse_num(arg, value)?,
+                "--filter" => o.filter = value.parse().map_err(invalid)?,
+                "--filter-radius" => {
+                    let r: f64 = parse_float(arg, value)?;
+                    // wider filters only blur and make every tile render
+                    // its whole neighbourhood
+                    if r <= 0. || r > MAX_FILTER_RADIUS {
+                        return Err(invalid(format!(
+                            "{} must be above 0 and at most {}, got: {}",
+                            arg, MAX_FILTER_RADIUS, value
+                        )));
+                    }
+                    o.filter_radius = Some(r);
+                }
+                "-t" | "--threads" => o.threads = parse_count(arg, value)?,
+                "-d" | "--depth" => o.max_depth = parse_num(arg, value)?,
+                "--integrator" => o.integrator = value.parse().map_err(invalid)?,
+                "--accel" => o.accel = value.parse().map_err(invalid)?,
+                "--lenient" => o.lenient = parse_switch(arg, value)?,
+                "--search-path" => o.search_path.extend(env::split_paths(value)),
+                "--texture-filter" => o.texture_filter = value.parse().map_err(invalid)?,
+                "--eye" => o.eye = parse_vec(arg, value, 1.)?,
+                "--look-at" => o.look_at = Some(parse_vec(arg, value, 1.)?),
+                "--up" => o.up = parse_vec(arg, value, 0.)?,
+                "--fov" => {
+                    o.fov = parse_float(arg, value)?;
+                    if o.fov <= 0. || o.fov >= 180. {
+                        return Err(invalid(format!("{} must be between 0 and 180", arg)));
+                    }
+                }
+                "--aperture" => {
+                    o.aperture = parse_float(arg, value)?;
+                    if o.aperture < 0. {
+                        return Err(invalid(format!("{} can't be negative", arg)));
+                    }
+                }
+                "--focus-dist" => {
+                    let d: f64 = parse_float(arg, value)?;
+                    if d <= 0. {
+                        return Err(invalid(format!("{} must be positive", arg)));
+                    }
+                    o.focus_dist = Some(d);
+                }
+                _ => return Err(invalid(format!("unknown option: {}", arg))),
+            }
+        }
+
+        if let Some(i) = input {
+            o.input = i;
+        }
+        o.format = match format.or_else(|| OutputFormat::from_path(&o.output)) {
+            Some(f) => f,
+            None => {
+                return Err(invalid(format!(
+                    "can't guess the format of {}, use --format",
+                    o.output
+                )))
+            }
+        };
+
+        return Ok(o);
+    }
+}
+
+fn invalid(msg: String) -> CliError {
+    return CliError::Invalid(msg);
+}
+
+fn parse_num<T: FromStr>(arg: &str, value: &str) -> Result<T, CliError> {
+    return value
+        .parse::<T>()
+        .map_err(|_| invalid(format!("invalid value for {}: {}", arg, value)));
+}
+
+// f64 parses nan and inf too, which every range check below lets through
+fn parse_float(arg: &str, value: &str) -> Result<f64, CliError> {
+    let v: f64 = parse_num(arg, value)?;
+    if !v.is_finite() {
+        return Err(invalid(format!(
+            "{} must be a finite number, got: {}",
+            arg, value
+        )));
+    }
+    return Ok(v);
+}
+
+fn parse_switch(arg: &str, value: &str) -> Result<bool, CliError> {
+    match value {
+        "on" | "yes" | "true" | "1" => Ok(true),
+        "off" | "no" | "false" | "0" => Ok(false),
+        _ => Err(invalid(format!("{} takes on or off, got: {}", arg, value))),
+    }
+}
+
+fn parse_count(arg: &str, value: &str) -> Result<usize, CliError> {
+    let n: usize = parse_num(arg, value)?;
+    if n == 0 {
+        return Err(invalid(format!("{} must be at least 1", arg)));
+    }
+    return Ok(n);
+}
+
+fn parse_vec(arg: &str, value: &str, w: f64) -> Result<Vec4, CliError> {
+    let c: Vec<f64> = value
+        .split(',')
+        .map(|s| parse_float(arg, s.trim()))
+        .collect::<Result<Vec<f64>, CliError>>()?;
+    if c.len() != 3 {
+        return Err(invalid(format!("{} takes x,y,z, got: {}", arg, value)));
+    }
+    return Ok(Vec4::new(c[0], c[1], c[2], w));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, CliError> {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        return Options::parse(&args);
+    }
+
+    fn rejected(args: &[&str]) -> bool {
+        return matches!(parse(args), Err(CliError::Invalid(_)));
+    }
+
+    #[test]
+    fn accepts_finite_values() {
+        let o = parse(&["--fov", "40", "--aperture", "0.1", "--exposure", "-1.5"])
+            .ok()
+            .unwrap();
+        assert_eq!((o.fov, o.aperture, o.exposure), (40., 0.1, -1.5));
+        let o = parse(&["--filter-radius", "16"]).ok().unwrap();
+        assert_eq!(o.filter_radius, Some(16.));
+        let o = parse(&["--eye", "1, 2, 3"]).ok().unwrap();
+        assert_eq!((o.eye.x, o.eye.y, o.eye.z, o.eye.w), (1., 2., 3., 1.));
+    }
+
+    #[test]
+    fn rejects_nan_and_inf() {
+        for value in ["NaN", "nan", "inf", "-inf", "infinity"] {
+            for option in [
+                "--fov",
+                "--aperture",
+                "--exposure",
+                "--white",
+                "--focus-dist",
+                "--filter-radius",
+            ] {
+                assert!(rejected(&[option, value]), "{} {}", option, value);
+            }
+        }
+        assert!(rejected(&["--eye", "0,nan,0"]));
+        assert!(rejected(&["--up", "0,inf,0"]));
+    }
+
+    #[test]
+    fn rejects_out_of_range() {
+        assert!(rejected(&["--fov", "180"]));
+        assert!(rejected(&["--aperture", "-1"]));
+        assert!(rejected(&["--focus-dist", "0"]));
+        assert!(rejected(&["--filter-radius", "0"]));
+        assert!(rejected(&["--filter-radius", "17"]));
+        assert!(rejected(&["--filter-radius", "1e300"]));
+        assert!(rejected(&["--fov", "wide"]));
+    }
+}
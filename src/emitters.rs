@@ -0,0 +1,108 @@
+use crate::material::Material;
+use crate::triangle::Triangle;
+use crate::vec4::Vec4;
+
+// every triangle with a non-zero ke is an area light, emission is two sided
+// like blender's emissive planes
+
+pub struct EmitterSample {
+    pub p: Vec4,  // point on the light
+    pub wi: Vec4, // normalized direction from the shading point to p
+    pub le: Vec4, // emitted radiance towards the shading point
+    pub pdf: f64, // solid angle pdf of picking wi
+}
+
+pub struct Emitters {
+    pub triangles: Vec<Triangle>,
+    emission: Vec<Vec4>,
+    cdf: Vec<f64>,
+    total_power: f64,
+}
+
+fn power(t: &Triangle, le: &Vec4) -> f64 {
+    return t.area() * le.luminance();
+}
+
+impl Emitters {
+    pub fn new(triangles: &[Triangle], materials: &[Material]) -> Emitters {
+        // lights are picked proportionally to their power, so small dim
+        // emitters don't steal samples from the big bright ones
+        let mut emitters: Vec<Triangle> = Vec::new();
+        let mut emission: Vec<Vec4> = Vec::new();
+        let mut cdf: Vec<f64> = Vec::new();
+        let mut total_power: f64 = 0.;
+
+        for t in triangles {
+            let le: Vec4 = materials[t.mat].ke;
+            if le.max3() <= 0. || power(t, &le) <= 0. {
+                continue;
+            }
+            total_power += power(t, &le);
+            emitters.push(*t);
+            emission.push(le);
+            cdf.push(total_power);
+        }
+
+        return Emitters {
+            triangles: emitters,
+            emission: emission,
+            cdf: cdf,
+            total_power: total_power,
+        };
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.triangles.is_empty();
+    }
+
+    pub fn sample(&self, p: &Vec4, u0: f64, u1: f64, u2: f64) -> Option<EmitterSample> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let target = u0 * self.total_power;
+        let i = self
+            .cdf
+            .partition_point(|c| *c <= target)
+            .min(self.triangles.len() - 1);
+        let t: &Triangle = &self.triangles[i];
+
+        let q: Vec4 = t.sample_point(u1, u2);
+        let d: Vec4 = q - *p;
+        let dist = d.length();
+        if dist <= 0. {
+            return None;
+        }
+        let wi: Vec4 = d / dist;
+
+        let le: Vec4 = self.emission[i];
+        let pdf = self.pdf(t, &le, p, &q);
+        if pdf <= 0. || pdf.is_infinite() {
+            return None;
+        }
+
+        return Some(EmitterSample {
+            p: q,
+            wi: wi,
+            le: le,
+            pdf: pdf,
+        });
+    }
+
+    // solid angle pdf of sampling point q on emitter t (emitting le) as
+    // seen from p
+    pub fn pdf(&self, t: &Triangle, le: &Vec4, p: &Vec4, q: &Vec4) -> f64 {
+        if self.total_power <= 0. {
+            return 0.;
+        }
+        let d: Vec4 = *q - *p;
+        let dist2 = d.dot(d);
+        let cos_l = t.normal().dot(d.normalize()).abs();
+        if cos_l <= 0. {
+            return 0.;
+        }
+
+        let pick = power(t, le) / self.total_power;
+        return pick * dist2 / (cos_l * t.area());
+    }
+}
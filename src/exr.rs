@@ -0,0 +1,393 @@
+use crate::vec4::Vec4;
+use crate::zlib;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::str::FromStr;
+
+// single part scanline openexr, linear rgb with optional alpha
+// https://openexr.com/en/latest/OpenEXRFileLayout.html
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ExrCompression {
+    None,
+    Rle,
+    Zip,
+}
+
+impl FromStr for ExrCompression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ExrCompression, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(ExrCompression::None),
+            "rle" => Ok(ExrCompression::Rle),
+            "zip" => Ok(ExrCompression::Zip),
+            _ => Err(format!("unknown exr compression: {}", s)),
+        }
+    }
+}
+
+impl ExrCompression {
+    fn id(&self) -> u8 {
+        match self {
+            ExrCompression::None => 0,
+            ExrCompression::Rle => 1,
+            ExrCompression::Zip => 3,
+        }
+    }
+
+    fn lines_per_block(&self) -> usize {
+        match self {
+            ExrCompression::Zip => 16,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ExrPixelType {
+    Half,
+    Float,
+}
+
+impl FromStr for ExrPixelType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ExrPixelType, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "half" => Ok(ExrPixelType::Half),
+            "float" => Ok(ExrPixelType::Float),
+            _ => Err(format!("unknown exr pixel type: {}", s)),
+        }
+    }
+}
+
+pub fn write_exr(
+    path: &str,
+    screen: &[Vec4],
+    res_x: usize,
+    res_y: usize,
+    compression: ExrCompression,
+    pixel_type: ExrPixelType,
+    alpha: bool,
+) -> io::Result<()> {
+    // channels are stored in alphabetical order
+    let channels: &[(&str, usize)] = if alpha {
+        &[("A", 3), ("B", 2), ("G", 1), ("R", 0)]
+    } else {
+        &[("B", 2), ("G", 1), ("R", 0)]
+    };
+    let type_id: i32 = match pixel_type {
+        ExrPixelType::Half => 1,
+        ExrPixelType::Float => 2,
+    };
+
+    let mut header: Vec<u8> = Vec::new();
+    header.extend([0x76, 0x2f, 0x31, 0x01]); // magic
+    header.extend(2u32.to_le_bytes()); // version 2, single part scanline
+
+    let mut chlist: Vec<u8> = Vec::new();
+    for (name, _) in channels {
+        chlist.extend(name.as_bytes());
+        chlist.push(0);
+        chlist.extend(type_id.to_le_bytes());
+        chlist.extend([0, 0, 0, 0]); // not perceptually linear, reserved
+        chlist.extend(1i32.to_le_bytes()); // x sampling
+        chlist.extend(1i32.to_le_bytes()); // y sampling
+    }
+    chlist.push(0);
+
+    let mut window: Vec<u8> = Vec::new();
+    for v in [0, 0, res_x as i32 - 1, res_y as i32 - 1] {
+        window.extend(v.to_le_bytes());
+    }
+
+    attribute(&mut header, "channels", "chlist", &chlist);
+    attribute(
+        &mut header,
+        "compression",
+        "compression",
+        &[compression.id()],
+    );
+    attribute(&mut header, "dataWindow", "box2i", &window);
+    attribute(&mut header, "displayWindow", "box2i", &window);
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]); // increasing y
+    attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    let mut center: Vec<u8> = Vec::new();
+    center.extend(0f32.to_le_bytes());
+    center.extend(0f32.to_le_bytes());
+    attribute(&mut header, "screenWindowCenter", "v2f", &center);
+    attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    header.push(0);
+
+    // every block is some scanlines, channel by channel, each channel all of
+    // its pixels in a row
+    let lines = compression.lines_per_block();
+    let mut blocks: Vec<Vec<u8>> = Vec::new();
+    for y0 in (0..res_y).step_by(lines) {
+        let mut raw: Vec<u8> = Vec::new();
+        for y in y0..usize::min(y0 + lines, res_y) {
+            let row = &screen[y * res_x..(y + 1) * res_x];
+            for (_, c) in channels {
+                for px in row {
+                    let v = px.elem(*c) as f32;
+                    match pixel_type {
+                        ExrPixelType::Half => raw.extend(f32_to_half(v).to_le_bytes()),
+                        ExrPixelType::Float => raw.extend(v.to_le_bytes()),
+                    }
+                }
+            }
+        }
+
+        let packed = match compression {
+            ExrCompression::None => raw,
+            ExrCompression::Rle => keep_smaller(rle(&predict(&raw)), raw),
+            ExrCompression::Zip => keep_smaller(zlib::compress(&predict(&raw)), raw),
+        };
+
+        let mut block: Vec<u8> = Vec::with_capacity(packed.len() + 8);
+        block.extend((y0 as i32).to_le_bytes());
+        block.extend((packed.len() as i32).to_le_bytes());
+        block.extend(packed);
+        blocks.push(block);
+    }
+
+    let mut buf = BufWriter::new(File::create(path)?);
+    buf.write_all(&header)?;
+    let mut offset = (header.len() + blocks.len() * 8) as u64;
+    for block in &blocks {
+        buf.write_all(&offset.to_le_bytes())?;
+        offset += block.len() as u64;
+    }
+    for block in &blocks {
+        buf.write_all(block)?;
+    }
+    return buf.flush();
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend(name.as_bytes());
+    header.push(0);
+    header.extend(kind.as_bytes());
+    header.push(0);
+    header.extend((value.len() as i32).to_le_bytes());
+    header.extend(value);
+}
+
+// readers take a block that didn't get any smaller as uncompressed
+fn keep_smaller(packed: Vec<u8>, raw: Vec<u8>) -> Vec<u8> {
+    if packed.len() < raw.len() {
+        return packed;
+    }
+    return raw;
+}
+
+// rle and zip both first split the bytes into two halves, the even ones then
+// the odd ones, and store each as the difference to the one before it
+fn predict(raw: &[u8]) -> Vec<u8> {
+    let half = raw.len().div_ceil(2);
+    let mut t: Vec<u8> = vec![0; raw.len()];
+    for (i, b) in raw.iter().enumerate() {
+        if i % 2 == 0 {
+            t[i / 2] = *b;
+        } else {
+            t[half + i / 2] = *b;
+        }
+    }
+    let mut p = t.first().copied().unwrap_or(0);
+    for v in t.iter_mut().skip(1) {
+        let d = v.wrapping_sub(p).wrapping_add(128);
+        p = *v;
+        *v = d;
+    }
+    return t;
+}
+
+// runs of 3 to 128 equal bytes as (count - 1, byte), anything else as
+// (-count, bytes...) with up to 127 literal bytes
+fn rle(data: &[u8]) -> Vec<u8> {
+    const MIN_RUN: usize = 3;
+    const MAX_RUN: usize = 127;
+    let n = data.len();
+    let mut out: Vec<u8> = Vec::new();
+    let mut start = 0;
+    while start < n {
+        let mut end = start + 1;
+        while end < n && data[start] == data[end] && end - start - 1 < MAX_RUN {
+            end += 1;
+        }
+        if end - start >= MIN_RUN {
+            out.push((end - start - 1) as u8);
+            out.push(data[start]);
+        } else {
+            while end < n
+                && (end + 1 >= n
+                    || data[end] != data[end + 1]
+                    || end + 2 >= n
+                    || data[end + 1] != data[end + 2])
+                && end - start < MAX_RUN
+            {
+                end += 1;
+            }
+            out.push((-((end - start) as i32)) as u8);
+            out.extend(&data[start..end]);
+        }
+        start = end;
+    }
+    return out;
+}
+
+// round to nearest even ieee 754 half, out of range values become infinity
+fn f32_to_half(f: f32) -> u16 {
+    let bits = f.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exp = ((bits >> 23) & 0xff) as i32;
+    let mant = bits & 0x7fffff;
+
+    if exp == 0xff {
+        // inf stays inf, nan keeps a mantissa bit set
+        return sign | 0x7c00 | if mant != 0 { 0x200 } else { 0 };
+    }
+    let e = exp - 127 + 15;
+    if e >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if e <= 0 {
+        // subnormal half, or zero if it's too small even for that
+        if e < -10 {
+            return sign;
+        }
+        let m = mant | 0x800000;
+        let shift = (14 - e) as u32;
+        let half = m >> shift;
+        let rem = m & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        let round = rem > halfway || (rem == halfway && half & 1 != 0);
+        return sign | (half + round as u32) as u16;
+    }
+    let half = ((e as u32) << 10) | (mant >> 13);
+    let rem = mant & 0x1fff;
+    let round = rem > 0x1000 || (rem == 0x1000 && half & 1 != 0);
+    // a carry out of the mantissa correctly bumps the exponent
+    return sign | (half + round as u32) as u16;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // what a reader does to rle output
+    fn unrle(data: &[u8]) -> Vec<u8> {
+        let mut out: Vec<u8> = Vec::new();
+        let mut i = 0;
+        while i < data.len() {
+            let c = data[i] as i8;
+            if c < 0 {
+                let n = -(c as i32) as usize;
+                out.extend(&data[i + 1..i + 1 + n]);
+                i += 1 + n;
+            } else {
+                out.extend(std::iter::repeat_n(data[i + 1], c as usize + 1));
+                i += 2;
+            }
+        }
+        return out;
+    }
+
+    // and to the predictor
+    fn unpredict(t: &[u8]) -> Vec<u8> {
+        let mut t = t.to_vec();
+        for i in 1..t.len() {
+            t[i] = t[i - 1].wrapping_add(t[i]).wrapping_sub(128);
+        }
+        let half = t.len().div_ceil(2);
+        return (0..t.len())
+            .map(|i| {
+                if i % 2 == 0 {
+                    t[i / 2]
+                } else {
+                    t[half + i / 2]
+                }
+            })
+            .collect();
+    }
+
+    #[test]
+    fn half_zero_and_normals() {
+        assert_eq!(f32_to_half(0.), 0x0000);
+        assert_eq!(f32_to_half(-0.), 0x8000);
+        assert_eq!(f32_to_half(1.), 0x3c00);
+        assert_eq!(f32_to_half(-2.), 0xc000);
+        assert_eq!(f32_to_half(0.5), 0x3800);
+        // smallest normal
+        assert_eq!(f32_to_half(2f32.powi(-14)), 0x0400);
+    }
+
+    #[test]
+    fn half_subnormals() {
+        assert_eq!(f32_to_half(2f32.powi(-24)), 0x0001);
+        assert_eq!(f32_to_half(1023. * 2f32.powi(-24)), 0x03ff);
+        // halfway cases round to even
+        assert_eq!(f32_to_half(2f32.powi(-25)), 0x0000);
+        assert_eq!(f32_to_half(1.5 * 2f32.powi(-24)), 0x0002);
+        // far too small, but keeps its sign
+        assert_eq!(f32_to_half(-1e-10), 0x8000);
+    }
+
+    #[test]
+    fn half_max_and_overflow() {
+        assert_eq!(f32_to_half(65504.), 0x7bff);
+        // rounds up into the exponent, and past the largest half
+        assert_eq!(f32_to_half(2047.5), 0x6800);
+        assert_eq!(f32_to_half(65520.), 0x7c00);
+        assert_eq!(f32_to_half(f32::MAX), 0x7c00);
+        assert_eq!(f32_to_half(-f32::MAX), 0xfc00);
+    }
+
+    #[test]
+    fn half_infinity_and_nan() {
+        assert_eq!(f32_to_half(f32::INFINITY), 0x7c00);
+        assert_eq!(f32_to_half(f32::NEG_INFINITY), 0xfc00);
+        let nan = f32_to_half(f32::NAN);
+        assert_eq!(nan & 0x7c00, 0x7c00);
+        assert_ne!(nan & 0x03ff, 0);
+    }
+
+    #[test]
+    fn rle_round_trips() {
+        let mut data: Vec<u8> = vec![1, 2, 3, 3, 4, 4, 4, 4, 5];
+        data.extend(vec![9; 300]);
+        data.extend((0..=255).collect::<Vec<u8>>());
+        data.extend([6, 6]);
+        assert_eq!(unrle(&rle(&data)), data);
+        assert_eq!(unrle(&rle(&[])), Vec::<u8>::new());
+        assert_eq!(unrle(&rle(&[5])), vec![5]);
+    }
+
+    #[test]
+    fn rle_packs_runs() {
+        assert_eq!(rle(&[7; 128]), vec![127, 7]);
+        assert_eq!(rle(&[1, 2]), vec![(-2i8) as u8, 1, 2]);
+    }
+
+    #[test]
+    fn predictor_round_trips() {
+        for data in [vec![], vec![3], vec![1, 200, 3, 0, 255, 17, 9]] {
+            assert_eq!(unpredict(&predict(&data)), data);
+        }
+        // a smooth ramp turns into nearly constant bytes
+        let ramp: Vec<u8> = (0..64).collect();
+        assert!(predict(&ramp)[1..32].iter().all(|b| *b == 130));
+    }
+}
@@ -0,0 +1,88 @@
+use crate::filter::Filter;
+use crate::vec4::Vec4;
+
+// filter weighted sum of radiance samples over a rectangle of pixels, the
+// image is sum / weight. tiles render into their own film, grown by the
+// filter radius so samples near the edge reach the neighbouring pixels,
+// and get merged into the full image afterwards
+pub struct Film {
+    pub x0: usize,
+    pub y0: usize,
+    pub width: usize,
+    pub height: usize,
+    sum: Vec<Vec4>,
+    weight: Vec<f64>,
+}
+
+impl Film {
+    pub fn new(x0: usize, y0: usize, width: usize, height: usize) -> Film {
+        return Film {
+            x0: x0,
+            y0: y0,
+            width: width,
+            height: height,
+            sum: vec![Vec4::new(0., 0., 0., 0.); width * height],
+            weight: vec![0.; width * height],
+        };
+    }
+
+    // splat l at image position (x, y) into every pixel whose center is
+    // within the filter radius
+    pub fn add_sample(&mut self, x: f64, y: f64, l: Vec4, filter: &Filter) {
+        if !(l.x.is_finite() && l.y.is_finite() && l.z.is_finite()) {
+            return; // one bad path shouldn't blot out the pixels around it
+        }
+        let r = filter.radius;
+        let j0 = f64::max((x - 0.5 - r).ceil(), self.x0 as f64) as usize;
+        let j1 = f64::min((x - 0.5 + r).floor(), (self.x0 + self.width) as f64 - 1.);
+        let i0 = f64::max((y - 0.5 - r).ceil(), self.y0 as f64) as usize;
+        let i1 = f64::min((y - 0.5 + r).floor(), (self.y0 + self.height) as f64 - 1.);
+        if j1 < j0 as f64 || i1 < i0 as f64 {
+            return;
+        }
+
+        for i in i0..=i1 as usize {
+            for j in j0..=j1 as usize {
+                let w = filter.eval(j as f64 + 0.5 - x, i as f64 + 0.5 - y);
+                if w == 0. {
+                    continue;
+                }
+                let k = (i - self.y0) * self.width + (j - self.x0);
+                self.sum[k] += l * w;
+                self.weight[k] += w;
+            }
+        }
+    }
+
+    // add the overlapping part of other into this film
+    pub fn merge(&mut self, other: &Film) {
+        let x0 = usize::max(self.x0, other.x0);
+        let x1 = usize::min(self.x0 + self.width, other.x0 + other.width);
+        let y0 = usize::max(self.y0, other.y0);
+        let y1 = usize::min(self.y0 + self.height, other.y0 + other.height);
+
+        for i in y0..y1 {
+            for j in x0..x1 {
+                let a = (i - self.y0) * self.width + (j - self.x0);
+                let b = (i - other.y0) * other.width + (j - other.x0);
+                self.sum[a] += other.sum[b];
+                self.weight[a] += other.weight[b];
+            }
+        }
+    }
+
+    pub fn resolve(&self) -> Vec<Vec4> {
+        return self
+            .sum
+            .iter()
+            .zip(&self.weight)
+            .map(|(s, w)| {
+                if *w > 0. {
+                    *s / *w
+                } else {
+                    Vec4::new(0., 0., 0., 0.)
+                }
+            })
+            .collect();
+    }
+}
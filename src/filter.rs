@@ -0,0 +1,105 @@
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+// pixel reconstruction filters, separable and evaluated in pixel units
+// relative to the pixel center
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum FilterKind {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+    BlackmanHarris,
+}
+
+impl FromStr for FilterKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<FilterKind, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "box" => Ok(FilterKind::Box),
+            "tent" | "triangle" => Ok(FilterKind::Tent),
+            "gaussian" | "gauss" => Ok(FilterKind::Gaussian),
+            "mitchell" | "mitchell-netravali" => Ok(FilterKind::Mitchell),
+            "blackman-harris" | "blackman" => Ok(FilterKind::BlackmanHarris),
+            _ => Err(format!("unknown filter: {}", s)),
+        }
+    }
+}
+
+impl FilterKind {
+    pub fn default_radius(&self) -> f64 {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.,
+            FilterKind::BlackmanHarris => 2.,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Filter {
+    pub kind: FilterKind,
+    pub radius: f64, // in pixels, samples further away than this are ignored
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind, radius: Option<f64>) -> Filter {
+        return Filter {
+            kind: kind,
+            radius: radius.unwrap_or(kind.default_radius()),
+        };
+    }
+
+    pub fn eval(&self, x: f64, y: f64) -> f64 {
+        return self.eval_1d(x) * self.eval_1d(y);
+    }
+
+    fn eval_1d(&self, x: f64) -> f64 {
+        let r = self.radius;
+        let x = x.abs();
+        if x > r {
+            return 0.;
+        }
+        match self.kind {
+            FilterKind::Box => 1.,
+            FilterKind::Tent => r - x,
+            FilterKind::Gaussian => {
+                // shifted down so it reaches zero at the radius
+                let sigma = r / 3.;
+                let g = |x: f64| (-x * x / (2. * sigma * sigma)).exp();
+                g(x) - g(r)
+            }
+            FilterKind::Mitchell => mitchell(2. * x / r),
+            FilterKind::BlackmanHarris => {
+                let t = 0.5 + 0.5 * x / r;
+                0.35875 - 0.48829 * (2. * PI * t).cos() + 0.14128 * (4. * PI * t).cos()
+                    - 0.01168 * (6. * PI * t).cos()
+            }
+        }
+    }
+}
+
+// mitchell-netravali cubic with b = c = 1/3 on [0, 2], has small negative lobes
+// https://www.cs.utexas.edu/~fussell/courses/cs384g-fall2013/lectures/mitchell/Mitchell.pdf
+fn mitchell(x: f64) -> f64 {
+    let b = 1. / 3.;
+    let c = 1. / 3.;
+    if x < 1. {
+        return ((12. - 9. * b - 6. * c) * x * x * x
+            + (-18. + 12. * b + 6. * c) * x * x
+            + (6. - 2. * b))
+            / 6.;
+    }
+    if x < 2. {
+        return ((-b - 6. * c) * x * x * x
+            + (6. * b + 30. * c) * x * x
+            + (-12. * b - 48. * c) * x
+            + (8. * b + 24. * c))
+            / 6.;
+    }
+    return 0.;
+}
@@ -0,0 +1,170 @@
+use crate::vec4::Vec4;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+// linear float image formats that keep everything above 1, nothing is
+// clamped or gamma corrected
+
+// portable float map, rgb f32 rows stored bottom to top, a negative scale
+// means little endian
+// https://www.pauldebevec.com/Research/HDR/PFM/
+pub fn write_pfm(path: &str, screen: &[Vec4], res_x: usize, res_y: usize) -> io::Result<()> {
+    let mut buf = BufWriter::new(File::create(path)?);
+    write!(buf, "PF\n{} {}\n-1.0\n", res_x, res_y)?;
+    for y in (0..res_y).rev() {
+        for px in &screen[y * res_x..(y + 1) * res_x] {
+            for v in [px.x, px.y, px.z] {
+                buf.write_all(&(v as f32).to_le_bytes())?;
+            }
+        }
+    }
+    return buf.flush();
+}
+
+// radiance rgbe, a shared exponent per pixel with run length encoded
+// scanlines, see greg ward's "real pixels" in graphics gems ii
+pub fn write_rgbe(path: &str, screen: &[Vec4], res_x: usize, res_y: usize) -> io::Result<()> {
+    let mut buf = BufWriter::new(File::create(path)?);
+    write!(
+        buf,
+        "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+        res_y, res_x
+    )?;
+
+    let mut line: Vec<[u8; 4]> = Vec::with_capacity(res_x);
+    let mut out: Vec<u8> = Vec::new();
+    for y in 0..res_y {
+        line.clear();
+        line.extend(screen[y * res_x..(y + 1) * res_x].iter().map(rgbe));
+
+        out.clear();
+        // the run length scheme only exists for these widths
+        if (8..32768).contains(&res_x) {
+            out.extend([2, 2, (res_x >> 8) as u8, (res_x & 0xff) as u8]);
+            let mut component: Vec<u8> = Vec::with_capacity(res_x);
+            for c in 0..4 {
+                component.clear();
+                component.extend(line.iter().map(|p| p[c]));
+                rle_component(&component, &mut out);
+            }
+        } else {
+            out.extend(line.iter().flatten());
+        }
+        buf.write_all(&out)?;
+    }
+    return buf.flush();
+}
+
+fn rgbe(px: &Vec4) -> [u8; 4] {
+    let r = px.x.max(0.);
+    let g = px.y.max(0.);
+    let b = px.z.max(0.);
+    let v = r.max(g).max(b);
+    if v < 1e-32 || !v.is_finite() {
+        return [0, 0, 0, 0];
+    }
+    // v = m * 2^e with m in [0.5, 1)
+    let mut e = v.log2().floor() as i32 + 1;
+    if v / 2f64.powi(e) >= 1. {
+        e += 1;
+    }
+    let scale = 256. / 2f64.powi(e);
+    let q = |c: f64| f64::min(c * scale, 255.) as u8;
+    return [q(r), q(g), q(b), (e + 128).clamp(0, 255) as u8];
+}
+
+// a count above 128 repeats the next byte count - 128 times, otherwise that
+// many literal bytes follow
+fn rle_component(data: &[u8], out: &mut Vec<u8>) {
+    const MIN_RUN: usize = 4; // shorter runs are cheaper as literals
+    let n = data.len();
+    let mut cur = 0;
+    while cur < n {
+        let mut beg_run = cur;
+        let mut run_count = 0;
+        let mut old_run_count = 0;
+        while run_count < MIN_RUN && beg_run < n {
+            beg_run += run_count;
+            old_run_count = run_count;
+            run_count = 1;
+            while beg_run + run_count < n
+                && run_count < 127
+                && data[beg_run] == data[beg_run + run_count]
+            {
+                run_count += 1;
+            }
+        }
+        // a short run right before the long one still beats literals
+        if old_run_count > 1 && old_run_count == beg_run - cur {
+            out.push(128 + old_run_count as u8);
+            out.push(data[cur]);
+            cur = beg_run;
+        }
+        while cur < beg_run {
+            let count = usize::min(128, beg_run - cur);
+            out.push(count as u8);
+            out.extend(&data[cur..cur + count]);
+            cur += count;
+        }
+        if run_count >= MIN_RUN {
+            out.push(128 + run_count as u8);
+            out.push(data[beg_run]);
+            cur += run_count;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unrle(data: &[u8]) -> Vec<u8> {
+        let mut out: Vec<u8> = Vec::new();
+        let mut i = 0;
+        while i < data.len() {
+            let c = data[i] as usize;
+            if c > 128 {
+                out.extend(std::iter::repeat_n(data[i + 1], c - 128));
+                i += 2;
+            } else {
+                assert!(c > 0, "zero length literal");
+                out.extend(&data[i + 1..i + 1 + c]);
+                i += 1 + c;
+            }
+        }
+        return out;
+    }
+
+    fn encode(data: &[u8]) -> Vec<u8> {
+        let mut out: Vec<u8> = Vec::new();
+        rle_component(data, &mut out);
+        return out;
+    }
+
+    #[test]
+    fn rle_runs_and_literals() {
+        let line: Vec<u8> = vec![1, 2, 3, 9, 9, 9, 9, 9, 9, 4, 5, 5, 6];
+        let out = encode(&line);
+        assert_eq!(unrle(&out), line);
+        // the six nines are a single run between two literal blocks
+        assert_eq!(out, vec![3, 1, 2, 3, 128 + 6, 9, 4, 4, 5, 5, 6]);
+    }
+
+    #[test]
+    fn rle_long_runs_and_literals_split() {
+        let mut line: Vec<u8> = vec![0; 300];
+        line.extend((0..200).map(|i| i as u8));
+        line.extend([8, 8, 8, 1]);
+        let out = encode(&line);
+        assert_eq!(unrle(&out), line);
+        // runs stop at 127, literal blocks at 128
+        assert_eq!(&out[..2], &[128 + 127, 0]);
+    }
+
+    #[test]
+    fn rgbe_encodes_exponent() {
+        assert_eq!(rgbe(&Vec4::new(0., 0., 0., 1.)), [0, 0, 0, 0]);
+        assert_eq!(rgbe(&Vec4::new(1., 0.5, 0., 1.)), [128, 64, 0, 129]);
+    }
+}
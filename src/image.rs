@@ -0,0 +1,192 @@
+use crate::bmp;
+use crate::png;
+use crate::tga;
+use crate::vec4::Vec4;
+
+use std::fs;
+use std::path::Path;
+
+// a decoded image in floats, rows top to bottom, channels scaled to 0..1
+// as stored in the file, srgb_to_linear makes colour images linear. w is
+// alpha, 1 for images without it
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec4>,
+    pub alpha: bool, // whether the file had an alpha channel
+}
+
+impl Image {
+    pub fn read(path: &Path) -> Result<Image, String> {
+        let data: Vec<u8> = fs::read(path).map_err(|e| e.to_string())?;
+        // tga has no signature to go by, only the extension
+        let tga = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("tga"));
+        match data.get(0..2) {
+            Some(b"P2") | Some(b"P3") | Some(b"P5") | Some(b"P6") => read_pnm(&data),
+            Some(b"BM") => bmp::read_bmp(&data),
+            Some([0x89, b'P']) => png::read_png(&data),
+            _ if tga => tga::read_tga(&data),
+            _ => Err("unsupported image format".to_string()),
+        }
+    }
+
+    pub fn texel(&self, x: usize, y: usize) -> Vec4 {
+        return self.pixels[y * self.width + x];
+    }
+
+    // colour images are normally stored srgb encoded, shading wants them
+    // linear. alpha is always linear
+    pub fn srgb_to_linear(&mut self) {
+        for p in &mut self.pixels {
+            *p = Vec4::new(srgb_decode(p.x), srgb_decode(p.y), srgb_decode(p.z), p.w);
+        }
+    }
+}
+
+// the decoders check a header's size against these before allocating for
+// it, every pixel costs a Vec4
+const MAX_DIMENSION: usize = 1 << 15;
+const MAX_PIXELS: usize = 1 << 26;
+
+// width * height of an image a decoder is willing to allocate
+pub fn checked_size(width: usize, height: usize) -> Result<usize, String> {
+    if width == 0 || height == 0 {
+        return Err("empty image".to_string());
+    }
+    return width
+        .checked_mul(height)
+        .filter(|n| width <= MAX_DIMENSION && height <= MAX_DIMENSION && *n <= MAX_PIXELS)
+        .ok_or(format!("image too large: {}x{}", width, height));
+}
+
+// inverse of the iec 61966-2-1 encoding
+fn srgb_decode(v: f64) -> f64 {
+    if v <= 0.04045 {
+        return v / 12.92;
+    }
+    return ((v + 0.055) / 1.055).powf(2.4);
+}
+
+// netpbm header fields, whitespace separated with # comments to the end of
+// the line. returns the value and the offset just past it
+fn pnm_field(data: &[u8], mut pos: usize) -> Result<(usize, usize), String> {
+    loop {
+        match data.get(pos) {
+            Some(b'#') => {
+                while pos < data.len() && data[pos] != b'\n' {
+                    pos += 1;
+                }
+            }
+            Some(c) if c.is_ascii_whitespace() => pos += 1,
+            Some(_) => break,
+            None => return Err("truncated header".to_string()),
+        }
+    }
+    let start = pos;
+    while pos < data.len() && data[pos].is_ascii_digit() {
+        pos += 1;
+    }
+    let field = std::str::from_utf8(&data[start..pos]).unwrap_or("");
+    return field
+        .parse::<usize>()
+        .map(|v| (v, pos))
+        .map_err(|_| "invalid header".to_string());
+}
+
+// pgm and ppm, ascii (p2, p3) or binary (p5, p6), 8 or 16 bits per channel
+fn read_pnm(data: &[u8]) -> Result<Image, String> {
+    let channels = if data[1] == b'2' || data[1] == b'5' {
+        1
+    } else {
+        3
+    };
+    let (width, pos) = pnm_field(data, 2)?;
+    let (height, pos) = pnm_field(data, pos)?;
+    let (maxval, mut pos) = pnm_field(data, pos)?;
+    if maxval == 0 || maxval > 65535 {
+        return Err("invalid header".to_string());
+    }
+    let n = checked_size(width, height)? * channels;
+    let scale = 1. / maxval as f64;
+    let ascii = data[1] == b'2' || data[1] == b'3';
+
+    // ascii samples take at least two bytes each with the whitespace
+    let size = if maxval > 255 { 2 } else { 1 };
+    let start = pos + 1;
+    let available = data.len().saturating_sub(start);
+    if (ascii && n > available.div_ceil(2)) || (!ascii && n * size > available) {
+        return Err("truncated pixel data".to_string());
+    }
+
+    let mut samples: Vec<f64> = Vec::with_capacity(n);
+    if ascii {
+        for _ in 0..n {
+            let (v, next) = pnm_field(data, pos).map_err(|_| "truncated pixel data")?;
+            samples.push(v.min(maxval) as f64 * scale);
+            pos = next;
+        }
+    } else {
+        // a single whitespace byte separates the header from the samples
+        let body = data
+            .get(start..start + n * size)
+            .ok_or("truncated pixel data")?;
+        for s in body.chunks(size) {
+            let v = if size == 2 {
+                (s[0] as usize) << 8 | s[1] as usize
+            } else {
+                s[0] as usize
+            };
+            samples.push(v.min(maxval) as f64 * scale);
+        }
+    }
+
+    let pixels: Vec<Vec4> = samples
+        .chunks(channels)
+        .map(|c| Vec4::new(c[0], c[channels / 2], c[channels - 1], 1.))
+        .collect();
+    return Ok(Image {
+        width: width,
+        height: height,
+        pixels: pixels,
+        alpha: false,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size_limits() {
+        assert_eq!(checked_size(640, 480), Ok(640 * 480));
+        assert!(checked_size(0, 10).is_err());
+        assert!(checked_size(MAX_DIMENSION + 1, 1).is_err());
+        assert!(checked_size(MAX_DIMENSION, MAX_DIMENSION).is_err());
+        assert!(checked_size(usize::MAX, usize::MAX).is_err());
+    }
+
+    #[test]
+    fn reads_ascii_and_binary_pnm() {
+        let image = read_pnm(b"P3\n# a comment\n2 1\n255\n255 0 0  0 0 255\n").unwrap();
+        assert_eq!((image.width, image.height, image.alpha), (2, 1, false));
+        let rgb: Vec<[f64; 3]> = image.pixels.iter().map(|p| [p.x, p.y, p.z]).collect();
+        assert_eq!(rgb, vec![[1., 0., 0.], [0., 0., 1.]]);
+
+        // 16 bit samples are big endian
+        let image = read_pnm(b"P5 2 1 65535\n\xff\xff\x00\x00").unwrap();
+        let grey: Vec<f64> = image.pixels.iter().map(|p| p.x).collect();
+        assert_eq!(grey, vec![1., 0.]);
+    }
+
+    #[test]
+    fn rejects_huge_or_truncated_pnm() {
+        assert!(read_pnm(b"P6 4000000000 4000000000 255\n").is_err());
+        assert!(read_pnm(b"P6 99999999999999999999999 1 255\n").is_err());
+        assert!(read_pnm(b"P2 1000 1000 255\n1 2 3").is_err());
+        assert!(read_pnm(b"P5 4 4 255\n\x00\x01").is_err());
+        assert!(read_pnm(b"P5 4 4 0\n").is_err());
+        assert!(read_pnm(b"P3 1").is_err());
+    }
+}
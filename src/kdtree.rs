@@ -1,111 +1,426 @@
 use crate::aabb::AABB;
+use crate::accel::Accel;
 use crate::tracer::Ray;
 use crate::triangle::Triangle;
-use crate::vec4::Vec4;
-use std::sync::{Arc, Mutex};
+use std::cmp::Ordering;
+use std::sync::Arc;
 
-const MAX_DEPTH: usize = 2;
+// sah costs of one traversal step and one triangle test, relative to each other
+const TRAVERSAL_COST: f64 = 1.;
+const INTERSECT_COST: f64 = 1.5;
+// splits that cut off empty space get cheaper so big empty regions are
+// culled early
+const EMPTY_BONUS: f64 = 0.8;
 
 pub struct KDNode {
     pub aabb: AABB,
+    pub axis: usize,
+    pub split: f64,
     pub lt: Option<Arc<KDNode>>,
     pub gt: Option<Arc<KDNode>>,
-    pub triangles: Option<Vec<Triangle>>,
+    pub triangles: Option<Vec<usize>>, // indices into Obj::triangles
 }
 
-// TODO clean this up, it works but really messy right now
-// also use SAH for choosing split
+// where a triangle's bounds start or end along an axis, or where it lies
+// flat in a plane perpendicular to it. the order is the order events at the
+// same position get swept in
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum EventType {
+    End,
+    Planar,
+    Start,
+}
+
+#[derive(Copy, Clone)]
+struct Event {
+    tri: usize,
+    axis: usize,
+    pos: f64,
+    kind: EventType,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Side {
+    Left,
+    Right,
+    Both,
+}
+
+struct Split {
+    axis: usize,
+    pos: f64,
+    cost: f64,
+    planar: Side, // which child gets triangles lying in the split plane
+}
+
+// sah kd-tree after wald and havran, "on building fast kd-trees for ray
+// tracing, and on doing that in O(N log N)"
+// https://www.irisa.fr/prive/kadi/Sujets_CTR/kadi/Kadi_sujet2_article_Kdtree.pdf
+// the events are sorted once up front and split into already sorted child
+// lists, only triangles straddling a split get new events that need sorting
 impl KDNode {
-    pub fn new(triangles: &Vec<Triangle>, depth: usize, aabb: &AABB) -> KDNode {
-        if triangles.len() == 0 {
-            return KDNode {
-                aabb: *aabb,
-                lt: None,
-                gt: None,
-                triangles: None,
-            };
-        }
-        if (depth == MAX_DEPTH) {
-            let mut nt: Vec<Triangle> = Vec::new();
-            for t in triangles {
-                nt.push(*t);
+    pub fn new(triangles: &[Triangle], aabb: &AABB) -> KDNode {
+        let mut events: Vec<Event> = Vec::with_capacity(triangles.len() * 6);
+        for (i, t) in triangles.iter().enumerate() {
+            if let Some(bb) = aabb.clip_triangle(t) {
+                push_events(&mut events, i, &bb);
             }
-            return KDNode {
-                aabb: *aabb,
-                lt: None,
-                gt: None,
-                triangles: Some(nt),
-            };
-        }
-        let axis = depth % 3;
-        let mut midpoints: Vec<f64> = Vec::new();
-        for t in triangles {
-            midpoints.push(t.midpoint().elem(axis));
         }
-        midpoints.sort_by(|a, b| a.partial_cmp(b).unwrap());
-        let median: f64 = midpoints[midpoints.len() / 2]; // TODO: use linear time median
-
-        let mut lt_v: Vec4 = aabb.max;
-        lt_v.set_elem(axis, median);
-        let mut gt_v: Vec4 = aabb.min;
-        gt_v.set_elem(axis, median);
-        let epsilon = Vec4::new(0.001, 0.001, 0.001, 0.);
-
-        let bb_lt = AABB {
-            min: aabb.min - epsilon,
-            max: lt_v + epsilon,
-        };
+        events.sort_by(cmp_events);
+
+        // a safety net against pathological input, sah stops well before it
+        let n = triangles.len().max(1) as f64;
+        let max_depth = (8. + 1.3 * n.log2()) as usize;
+
+        let mut sides: Vec<Side> = vec![Side::Both; triangles.len()];
+        return Self::build(triangles, events, aabb, 0, max_depth, &mut sides);
+    }
+
+    fn build(
+        triangles: &[Triangle],
+        events: Vec<Event>,
+        aabb: &AABB,
+        depth: usize,
+        max_depth: usize,
+        sides: &mut [Side],
+    ) -> KDNode {
+        // every triangle has exactly one start or planar event per axis
+        let n = events
+            .iter()
+            .filter(|e| e.axis == 0 && e.kind != EventType::End)
+            .count();
 
-        let bb_gt = AABB {
-            min: gt_v - epsilon,
-            max: aabb.max + epsilon,
+        let split = match find_split(&events, n, aabb) {
+            Some(s) if depth < max_depth && s.cost < INTERSECT_COST * n as f64 => s,
+            _ => return Self::leaf(&events, aabb),
         };
 
-        let mut tlt: Vec<Triangle> = Vec::new();
-        let mut tgt: Vec<Triangle> = Vec::new();
+        classify(&events, &split, sides);
+        let (bb_lt, bb_gt) = split_box(aabb, split.axis, split.pos);
 
-        for t in triangles {
-            if bb_lt.intersect_triangle(&t) {
-                tlt.push(*t);
-            }
-            if bb_gt.intersect_triangle(&t) {
-                tgt.push(*t);
+        let mut lt_only: Vec<Event> = Vec::new();
+        let mut gt_only: Vec<Event> = Vec::new();
+        let mut lt_new: Vec<Event> = Vec::new();
+        let mut gt_new: Vec<Event> = Vec::new();
+        for e in &events {
+            match sides[e.tri] {
+                Side::Left => lt_only.push(*e),
+                Side::Right => gt_only.push(*e),
+                Side::Both => {
+                    // straddling triangles are clipped to each child so their
+                    // events stay tight, once per triangle
+                    if e.axis == 0 && e.kind != EventType::End {
+                        let t = &triangles[e.tri];
+                        if let Some(bb) = bb_lt.clip_triangle(t) {
+                            push_events(&mut lt_new, e.tri, &bb);
+                        }
+                        if let Some(bb) = bb_gt.clip_triangle(t) {
+                            push_events(&mut gt_new, e.tri, &bb);
+                        }
+                    }
+                }
             }
         }
+        drop(events);
+        lt_new.sort_by(cmp_events);
+        gt_new.sort_by(cmp_events);
+
+        let lt_events = merge(lt_only, lt_new);
+        let gt_events = merge(gt_only, gt_new);
 
-        println!("median {}: {} on {}", depth, median, axis);
-        println!("tlt: {}, tgt: {}", tlt.len(), tgt.len());
         return KDNode {
             aabb: *aabb,
-            lt: Some(Arc::new(Self::new(&tlt, depth + 1, &bb_lt))),
-            gt: Some(Arc::new(Self::new(&tgt, depth + 1, &bb_gt))),
+            axis: split.axis,
+            split: split.pos,
+            lt: Some(Arc::new(Self::build(
+                triangles,
+                lt_events,
+                &bb_lt,
+                depth + 1,
+                max_depth,
+                sides,
+            ))),
+            gt: Some(Arc::new(Self::build(
+                triangles,
+                gt_events,
+                &bb_gt,
+                depth + 1,
+                max_depth,
+                sides,
+            ))),
             triangles: None,
         };
     }
 
-    pub fn ray_leaf(&self, r: &Ray) -> Vec<Triangle> {
-        let mut leaf_triangles: Vec<Triangle> = Vec::new();
-        if self.triangles.is_none() {
-            if self.lt.as_ref().is_some() {
-                let alt = Arc::clone(&(self.lt.as_ref().unwrap()));
-                if alt.aabb.intersect_ray(r) {
-                    leaf_triangles.extend(alt.ray_leaf(r));
+    fn leaf(events: &[Event], aabb: &AABB) -> KDNode {
+        let indices: Vec<usize> = events
+            .iter()
+            .filter(|e| e.axis == 0 && e.kind != EventType::End)
+            .map(|e| e.tri)
+            .collect();
+        return KDNode {
+            aabb: *aabb,
+            axis: 0,
+            split: 0.,
+            lt: None,
+            gt: None,
+            triangles: Some(indices),
+        };
+    }
+
+    // (leaves, triangle references in leaves, depth) for the build summary
+    pub fn stats(&self) -> (usize, usize, usize) {
+        if let Some(t) = &self.triangles {
+            return (1, t.len(), 0);
+        }
+        let mut s = (0, 0, 0);
+        for c in [&self.lt, &self.gt].into_iter().flatten() {
+            let (leaves, refs, depth) = c.stats();
+            s = (s.0 + leaves, s.1 + refs, usize::max(s.2, depth + 1));
+        }
+        return s;
+    }
+
+    // front to back traversal, recursing keeps the stack off the heap. stops
+    // once the closest hit so far is in front of the far child, or on the
+    // first hit at all for any-hit queries. returns true to stop early
+    fn traverse(
+        &self,
+        triangles: &[Triangle],
+        r: &Ray,
+        tmin: f64,
+        tmax: f64,
+        best: &mut (Option<usize>, f64),
+        any: bool,
+    ) -> bool {
+        if let Some(indices) = &self.triangles {
+            for i in indices {
+                if let Some(t) = triangles[*i].intersect(r) {
+                    if t < best.1 {
+                        *best = (Some(*i), t);
+                        if any {
+                            return true;
+                        }
+                    }
                 }
             }
+            return false;
+        }
+
+        let o = r.origin.elem(self.axis);
+        let d = r.dir.elem(self.axis);
+        let below_first = o < self.split || (o == self.split && d <= 0.);
+        let (near, far) = if below_first {
+            (&self.lt, &self.gt)
+        } else {
+            (&self.gt, &self.lt)
+        };
 
-            if self.lt.as_ref().is_some() {
-                let agt = Arc::clone(&(self.gt.as_ref().unwrap()));
-                if agt.aabb.intersect_ray(r) {
-                    leaf_triangles.extend(agt.ray_leaf(r));
+        let mut t_split = (self.split - o) / d;
+        if t_split.is_nan() {
+            t_split = f64::INFINITY;
+        }
+
+        if t_split > tmax || t_split <= 0. {
+            if let Some(n) = near {
+                return n.traverse(triangles, r, tmin, tmax, best, any);
+            }
+        } else if t_split < tmin {
+            if let Some(f) = far {
+                return f.traverse(triangles, r, tmin, tmax, best, any);
+            }
+        } else {
+            if let Some(n) = near {
+                if n.traverse(triangles, r, tmin, t_split, best, any) {
+                    return true;
                 }
             }
-        } else if self.triangles.is_some() {
-            leaf_triangles.extend(self.triangles.as_ref().unwrap());
+            if best.1 <= t_split {
+                return false;
+            }
+            if let Some(f) = far {
+                return f.traverse(triangles, r, t_split, tmax, best, any);
+            }
         }
+        return false;
+    }
+}
 
-        if (leaf_triangles.len() > 500) {
-            //println!("returning lots! {}", leaf_triangles.len());
+impl Accel for KDNode {
+    fn closest_hit(&self, triangles: &[Triangle], r: &Ray, tmax: f64) -> Option<(usize, f64)> {
+        let (t0, t1) = self.aabb.clip_ray(r)?;
+        let mut best: (Option<usize>, f64) = (None, tmax);
+        self.traverse(triangles, r, t0, f64::min(t1, tmax), &mut best, false);
+        return best.0.map(|i| (i, best.1));
+    }
+
+    fn any_hit(&self, triangles: &[Triangle], r: &Ray, tmax: f64) -> bool {
+        let (t0, t1) = match self.aabb.clip_ray(r) {
+            Some(x) => x,
+            None => return false,
+        };
+        let mut best: (Option<usize>, f64) = (None, tmax);
+        self.traverse(triangles, r, t0, f64::min(t1, tmax), &mut best, true);
+        return best.0.is_some();
+    }
+
+    fn summary(&self) -> String {
+        let (leaves, refs, depth) = self.stats();
+        return format!(
+            "kd-tree: {} leaves, depth {}, {:.1} triangles per leaf",
+            leaves,
+            depth,
+            refs as f64 / leaves as f64
+        );
+    }
+}
+
+fn push_events(events: &mut Vec<Event>, tri: usize, bb: &AABB) {
+    for axis in 0..3 {
+        let lo = bb.min.elem(axis);
+        let hi = bb.max.elem(axis);
+        if lo == hi {
+            events.push(Event {
+                tri: tri,
+                axis: axis,
+                pos: lo,
+                kind: EventType::Planar,
+            });
+        } else {
+            events.push(Event {
+                tri: tri,
+                axis: axis,
+                pos: lo,
+                kind: EventType::Start,
+            });
+            events.push(Event {
+                tri: tri,
+                axis: axis,
+                pos: hi,
+                kind: EventType::End,
+            });
+        }
+    }
+}
+
+fn cmp_events(a: &Event, b: &Event) -> Ordering {
+    return a
+        .axis
+        .cmp(&b.axis)
+        .then(a.pos.total_cmp(&b.pos))
+        .then(a.kind.cmp(&b.kind));
+}
+
+fn merge(a: Vec<Event>, b: Vec<Event>) -> Vec<Event> {
+    let mut out: Vec<Event> = Vec::with_capacity(a.len() + b.len());
+    let mut a = a.into_iter().peekable();
+    let mut b = b.into_iter().peekable();
+    loop {
+        let e = match (a.peek(), b.peek()) {
+            (Some(x), Some(y)) => {
+                if cmp_events(x, y) == Ordering::Greater {
+                    b.next()
+                } else {
+                    a.next()
+                }
+            }
+            (Some(_), None) => a.next(),
+            (None, Some(_)) => b.next(),
+            (None, None) => break,
+        };
+        out.extend(e);
+    }
+    return out;
+}
+
+fn split_box(aabb: &AABB, axis: usize, pos: f64) -> (AABB, AABB) {
+    let mut lt = *aabb;
+    let mut gt = *aabb;
+    lt.max.set_elem(axis, pos);
+    gt.min.set_elem(axis, pos);
+    return (lt, gt);
+}
+
+// cost of splitting at pos with nl triangles left, nr right and np in the
+// plane, along with the side the planar ones are cheaper on
+fn sah(aabb: &AABB, axis: usize, pos: f64, nl: usize, nr: usize, np: usize) -> Option<(f64, Side)> {
+    // a split on the boundary leaves everything where it was
+    if pos <= aabb.min.elem(axis) || pos >= aabb.max.elem(axis) {
+        return None;
+    }
+    let area = aabb.surface_area();
+    if area <= 0. {
+        return None;
+    }
+    let (lt, gt) = split_box(aabb, axis, pos);
+    let pl = lt.surface_area() / area;
+    let pr = gt.surface_area() / area;
+
+    let cost = |nl: usize, nr: usize| {
+        let bonus = if nl == 0 || nr == 0 { EMPTY_BONUS } else { 1. };
+        bonus * (TRAVERSAL_COST + INTERSECT_COST * (pl * nl as f64 + pr * nr as f64))
+    };
+    let cl = cost(nl + np, nr);
+    let cr = cost(nl, nr + np);
+    if cl < cr {
+        return Some((cl, Side::Left));
+    }
+    return Some((cr, Side::Right));
+}
+
+// sweep the sorted events of all three axes, keeping per axis counts of the
+// triangles on either side of the current plane
+fn find_split(events: &[Event], n: usize, aabb: &AABB) -> Option<Split> {
+    let mut nl = [0usize; 3];
+    let mut nr = [n; 3];
+    let mut best: Option<Split> = None;
+
+    let mut i = 0;
+    while i < events.len() {
+        let axis = events[i].axis;
+        let pos = events[i].pos;
+        let mut count = [0usize; 3]; // ends, planars, starts
+        while i < events.len() && events[i].axis == axis && events[i].pos == pos {
+            count[events[i].kind as usize] += 1;
+            i += 1;
+        }
+        let (ends, planars, starts) = (count[0], count[1], count[2]);
+
+        nr[axis] -= planars + ends;
+        if let Some((cost, planar)) = sah(aabb, axis, pos, nl[axis], nr[axis], planars) {
+            if best.as_ref().is_none_or(|b| cost < b.cost) {
+                best = Some(Split {
+                    axis: axis,
+                    pos: pos,
+                    cost: cost,
+                    planar: planar,
+                });
+            }
+        }
+        nl[axis] += starts + planars;
+    }
+    return best;
+}
+
+// decide for every triangle in the node which children it goes to
+fn classify(events: &[Event], split: &Split, sides: &mut [Side]) {
+    for e in events {
+        sides[e.tri] = Side::Both;
+    }
+    for e in events.iter().filter(|e| e.axis == split.axis) {
+        match e.kind {
+            EventType::End if e.pos <= split.pos => sides[e.tri] = Side::Left,
+            EventType::Start if e.pos >= split.pos => sides[e.tri] = Side::Right,
+            EventType::Planar => {
+                if e.pos < split.pos {
+                    sides[e.tri] = Side::Left;
+                } else if e.pos > split.pos {
+                    sides[e.tri] = Side::Right;
+                } else {
+                    sides[e.tri] = split.planar;
+                }
+            }
+            _ => {}
         }
-        return leaf_triangles;
     }
 }
@@ -0,0 +1,225 @@
+use crate::mipmap::TextureFilter;
+
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+// something wrong in a scene file, and where
+#[derive(Clone, Debug)]
+pub struct LoadError {
+    pub path: String,
+    pub line: usize,   // starts at 1, 0 if it's about the whole file
+    pub column: usize, // starts at 1, 0 if it's about the whole line
+    pub reason: String,
+}
+
+impl LoadError {
+    pub fn file(path: &str, reason: String) -> LoadError {
+        return LoadError {
+            path: path.to_string(),
+            line: 0,
+            column: 0,
+            reason: reason,
+        };
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path)?;
+        if self.line > 0 {
+            write!(f, ":{}", self.line)?;
+            if self.column > 0 {
+                write!(f, ":{}", self.column)?;
+            }
+        }
+        write!(f, ": {}", self.reason)
+    }
+}
+
+impl Error for LoadError {}
+
+#[derive(Clone, Default)]
+pub struct LoadOptions {
+    // skip malformed lines with a warning instead of giving up on the file
+    pub lenient: bool,
+    // where to look for mtl files and textures that aren't next to the file
+    // mentioning them
+    pub search_path: Vec<PathBuf>,
+    // how texture maps are filtered unless their -filter option says
+    pub texture_filter: TextureFilter,
+}
+
+impl LoadOptions {
+    // a file mentioned in a scene file in dir, relative names are tried in
+    // dir first and then along the search path
+    pub fn find(&self, name: &str, dir: &Path) -> Option<PathBuf> {
+        // exporters on windows write backslashes
+        for name in [name.to_string(), name.replace('\\', "/")] {
+            let p = Path::new(&name);
+            if p.is_absolute() {
+                if p.is_file() {
+                    return Some(p.to_path_buf());
+                }
+                continue;
+            }
+            for d in std::iter::once(dir).chain(self.search_path.iter().map(|d| d.as_path())) {
+                let candidate = d.join(p);
+                if candidate.is_file() {
+                    return Some(candidate);
+                }
+            }
+        }
+        return None;
+    }
+
+    // the rest of a line naming one or more files, which may have spaces in
+    // them. the longest run of words that names an existing file wins
+    pub fn find_files(
+        &self,
+        t: &mut Tokens,
+        dir: &Path,
+        what: &str,
+    ) -> Vec<Result<PathBuf, LoadError>> {
+        let mut words: Vec<(&str, usize)> = Vec::new();
+        while let Some(w) = t.next_word() {
+            words.push((w, t.column));
+        }
+        if words.is_empty() {
+            return vec![Err(t.error(format!("expected {}", what)))];
+        }
+
+        let mut files: Vec<Result<PathBuf, LoadError>> = Vec::new();
+        let mut i = 0;
+        while i < words.len() {
+            let found = (i..words.len()).rev().find_map(|j| {
+                let name: Vec<&str> = words[i..=j].iter().map(|w| w.0).collect();
+                return self.find(&name.join(" "), dir).map(|p| (j, p));
+            });
+            match found {
+                Some((j, p)) => {
+                    files.push(Ok(p));
+                    i = j + 1;
+                }
+                None => {
+                    files.push(Err(t.error_at(
+                        words[i].1,
+                        format!("can't find {}: {}", what, words[i].0),
+                    )));
+                    i += 1;
+                }
+            }
+        }
+        return files;
+    }
+}
+
+// the whitespace separated words of one line, keeping track of where each
+// starts so errors can point at it
+pub struct Tokens<'a> {
+    path: &'a str,
+    line: usize,
+    text: &'a str,
+    pos: usize,    // byte offset of the unread rest of the line
+    column: usize, // of the last word read
+}
+
+impl<'a> Tokens<'a> {
+    pub fn new(path: &'a str, line: usize, text: &'a str) -> Tokens<'a> {
+        return Tokens {
+            path: path,
+            line: line,
+            text: text,
+            pos: 0,
+            column: 0,
+        };
+    }
+
+    pub fn next_word(&mut self) -> Option<&'a str> {
+        let rest = &self.text[self.pos..];
+        let start = self.pos + (rest.len() - rest.trim_start().len());
+        let len = self.text[start..]
+            .find(char::is_whitespace)
+            .unwrap_or(self.text.len() - start);
+        self.pos = start + len;
+        if len == 0 {
+            self.column = self.column_of(self.text.trim_end().len());
+            return None;
+        }
+        self.column = self.column_of(start);
+        return Some(&self.text[start..start + len]);
+    }
+
+    // the next word without consuming it
+    pub fn peek(&self) -> Option<&'a str> {
+        return self.text[self.pos..].split_whitespace().next();
+    }
+
+    // the next word, or an error naming what should have been there
+    pub fn expect(&mut self, what: &str) -> Result<&'a str, LoadError> {
+        return self
+            .next_word()
+            .ok_or_else(|| self.error(format!("expected {}", what)));
+    }
+
+    pub fn parse<T: FromStr>(&mut self, what: &str) -> Result<T, LoadError> {
+        let word = self.expect(what)?;
+        return word
+            .parse::<T>()
+            .map_err(|_| self.error(format!("invalid {}: {}", what, word)));
+    }
+
+    // like parse, for values that may be left out at the end of a line
+    pub fn parse_opt<T: FromStr>(&mut self, what: &str) -> Result<Option<T>, LoadError> {
+        match self.next_word() {
+            Some(word) => word
+                .parse::<T>()
+                .map(Some)
+                .map_err(|_| self.error(format!("invalid {}: {}", what, word))),
+            None => Ok(None),
+        }
+    }
+
+    // everything after the words read so far, minus surrounding whitespace
+    pub fn rest(&mut self) -> &'a str {
+        let rest = &self.text[self.pos..];
+        let start = self.pos + (rest.len() - rest.trim_start().len());
+        self.column = self.column_of(start);
+        self.pos = self.text.len();
+        return self.text[start..].trim_end();
+    }
+
+    // of the last word read
+    pub fn column(&self) -> usize {
+        return self.column;
+    }
+
+    // an error at the last word read
+    pub fn error(&self, reason: String) -> LoadError {
+        return self.error_at(self.column, reason);
+    }
+
+    pub fn error_at(&self, column: usize, reason: String) -> LoadError {
+        return LoadError {
+            path: self.path.to_string(),
+            line: self.line,
+            column: column,
+            reason: reason,
+        };
+    }
+
+    // an error about the line as a whole
+    pub fn line_error(&self, reason: String) -> LoadError {
+        return LoadError {
+            path: self.path.to_string(),
+            line: self.line,
+            column: 0,
+            reason: reason,
+        };
+    }
+
+    fn column_of(&self, byte: usize) -> usize {
+        return self.text[..byte].chars().count() + 1;
+    }
+}
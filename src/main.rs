@@ -1,76 +1,135 @@
+// explicit returns, `x: x` struct initializers and upper case acronyms
+// like AABB are the house style
+#![allow(
+    clippy::needless_return,
+    clippy::redundant_field_names,
+    clippy::upper_case_acronyms
+)]
+
 mod aabb;
+mod accel;
+mod bmp;
+mod bsdf;
+mod bvh;
+mod camera;
+mod cli;
+mod emitters;
+mod exr;
+mod film;
+mod filter;
+mod hdr;
+mod image;
 mod kdtree;
+mod load;
 mod mat4;
 mod material;
+mod microfacet;
+mod mipmap;
 mod obj;
+mod png;
+mod polygon;
+mod rng;
+mod sampler;
+mod sampling;
+mod texture;
+mod tga;
+mod tonemap;
 mod tracer;
 mod triangle;
 mod vec4;
-
-use crate::{aabb::AABB, mat4::Mat4, obj::Obj, tracer::Light, vec4::Vec4};
-
-use std::{fs::File, io::BufWriter, io::Write};
-
-use std::sync::{Arc, Mutex};
-use std::thread;
-
-use std::ops::Deref;
-
-fn write_screen(path: &'static str, screen: &Vec<Vec4>, res_x: usize, res_y: usize) {
-    let file = File::create(path).unwrap();
+mod zlib;
+
+use crate::{
+    camera::Camera,
+    cli::{CliError, Options, OutputFormat, USAGE},
+    filter::Filter,
+    load::LoadOptions,
+    mat4::Mat4,
+    obj::Obj,
+    tonemap::ToneMapper,
+    tracer::{Light, RenderSettings},
+    vec4::Vec4,
+};
+
+use std::{fs::File, io, io::BufWriter, io::Write};
+
+use std::process;
+
+fn write_screen(path: &str, screen: &[Vec4], res_x: usize, res_y: usize) -> io::Result<()> {
+    let file = File::create(path)?;
     let mut buf = BufWriter::new(file);
 
-    writeln!(buf, "P3").unwrap();
-    writeln!(buf, "{} {}", res_x, res_y).unwrap();
-    writeln!(buf, "255").unwrap();
+    writeln!(buf, "P3")?;
+    writeln!(buf, "{} {}", res_x, res_y)?;
+    writeln!(buf, "255")?;
 
-    for i in 0..(res_x * res_y) {
-        let c: Vec4 = screen[i].as_rgb();
-        writeln!(buf, "{} {} {}", c.x, c.y, c.z).unwrap();
+    // display values from the tone mapper
+    for px in screen.iter().take(res_x * res_y) {
+        let q = |v: f64| (v.clamp(0., 1.) * 255.).round() as u8;
+        writeln!(buf, "{} {} {}", q(px.x), q(px.y), q(px.z))?;
     }
-    let _ = buf.flush();
-    println!("done writing: {}", path);
+    return buf.flush();
 }
 
 fn main() {
-    const RES_X: usize = 1000;
-    const RES_Y: usize = 1000;
-
-    let rx: f64 = -3.1415 / 2.;
-    let ry: f64 = 3.1415;
-    let rz: f64 = -(3.1415 / 8.);
-
-    let mut maz = [Vec4::new(0., 0., 0., 0.); 4];
-    maz[0] = Vec4::new(rz.cos(), -(rz.sin()), 0., 0.);
-    maz[1] = Vec4::new(rz.sin(), rz.cos(), 0., 0.);
-    maz[2] = Vec4::new(0., 0., 1., 0.);
-    maz[3] = Vec4::new(0., 0., 0., 1.);
-    println!("{}", maz[0]);
-
-    let mut max = [Vec4::new(0., 0., 0., 0.); 4];
-    max[0] = Vec4::new(1., 0., 0., 0.);
-    max[1] = Vec4::new(0., rx.cos(), -(rx.sin()), 0.);
-    max[2] = Vec4::new(0., rx.sin(), rx.cos(), 0.);
-    max[3] = Vec4::new(0., 0., 0., 1.);
-
-    let mut may = [Vec4::new(0., 0., 0., 0.); 4];
-    may[0] = Vec4::new(ry.cos(), 0., -(ry.sin()), 0.);
-    may[1] = Vec4::new(0., 1., 0., 0.);
-    may[2] = Vec4::new(ry.sin(), 0., ry.cos(), 0.);
-    may[3] = Vec4::new(0., 0., 0., 1.);
-
-    let mut mas = [Vec4::new(0., 0., 0., 0.); 4];
-    mas[0] = Vec4::new(20., 0., 0., 0.);
-    mas[1] = Vec4::new(0., 20., 0., 0.);
-    mas[2] = Vec4::new(0., 0., 20., 0.);
-    mas[3] = Vec4::new(0., 0., 0., 1.);
-
-    let mz: Mat4 = Mat4 { m: maz };
-    let mx: Mat4 = Mat4 { m: max };
-    let ms: Mat4 = Mat4 { m: mas };
-    let my: Mat4 = Mat4 { m: may };
-
-    let object: Obj = Obj::from_file("cornell-box.obj", &(Mat4::identity()));
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let opts: Options = match Options::parse(&args) {
+        Ok(o) => o,
+        Err(CliError::Help) => {
+            print!("{}", USAGE);
+            return;
+        }
+        Err(CliError::Invalid(msg)) => {
+            eprintln!("error: {}", msg);
+            eprintln!("run with --help for usage");
+            process::exit(2);
+        }
+    };
+
+    let settings = RenderSettings {
+        integrator: opts.integrator,
+        spp: opts.spp,
+        max_depth: opts.max_depth,
+        threads: opts.threads,
+        filter: Filter::new(opts.filter, opts.filter_radius),
+        sampler: opts.sampler,
+        seed: opts.seed,
+    };
+
+    // before loading, so bad camera arguments fail fast
+    let look_at: Vec4 = opts.look_at.unwrap_or(opts.eye - Vec4::new(0., 0., 1., 0.));
+    let camera: Camera = match Camera::new(
+        opts.eye,
+        look_at,
+        opts.up,
+        opts.fov,
+        opts.width as f64 / opts.height as f64,
+        opts.aperture,
+        opts.focus_dist,
+    ) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let load_options = LoadOptions {
+        lenient: opts.lenient,
+        search_path: opts.search_path.clone(),
+        texture_filter: opts.texture_filter,
+    };
+    let object: Obj =
+        match Obj::from_file(&opts.input, &(Mat4::identity()), opts.accel, &load_options) {
+            Ok(o) => o,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                process::exit(1);
+            }
+        };
+    for w in &object.warnings {
+        eprintln!("warning: {}", w);
+    }
     /*println!(
         "{} vertices, {} triangles",
         object.vertices.len(),
@@ -78,7 +137,7 @@ fn main() {
     );*/
 
     //let triangles = object.triangles;
-    let mut lights: Vec<Light> = Vec::new();
+    let lights: Vec<Light> = Vec::new();
 
     /*lights.push(Light{
         pos: Vec4::new(-30.,30.,10.,1.),
@@ -100,26 +159,51 @@ fn main() {
         col: Vec4::new(0.5, 0.1, 0.1, 1.),
     });*/
 
-    let mut screen = vec![Vec4::new(0., 0., 0., 1.); RES_X * RES_Y];
-    let cam: Vec4 = Vec4::new(-0.2345, 2.58355, 5., 1.);
-    let screen_mutex = Arc::new(Mutex::new(screen));
-
     println!("min: {}, max: {}", object.aabb.min, object.aabb.max);
-
-    tracer::raytrace(
-        &screen_mutex,
-        Box::leak(Box::new(object)), // has to be a better way to make these 'static
-        Box::leak(Box::new(cam)),
-        Box::leak(Box::new(lights)),
-        RES_X,
-        RES_Y,
-        Box::leak(Box::new(Mat4::identity())),
-    );
-
-    write_screen(
-        "out.ppm",
-        Arc::clone(&screen_mutex).lock().unwrap().deref(),
-        RES_X,
-        RES_Y,
-    );
+    println!("{} emissive triangles", object.emitters.triangles.len());
+
+    let screen: Vec<Vec4> =
+        tracer::raytrace(&object, &camera, &lights, opts.width, opts.height, settings);
+
+    let tonemapper = ToneMapper {
+        op: opts.tonemap,
+        exposure: opts.exposure,
+        white: opts.white,
+        transfer: opts.transfer,
+    };
+    let written = match opts.format {
+        OutputFormat::Ppm => write_screen(
+            &opts.output,
+            &tonemapper.apply(&screen),
+            opts.width,
+            opts.height,
+        ),
+        OutputFormat::Png => png::write_png(
+            &opts.output,
+            &tonemapper.apply(&screen),
+            opts.width,
+            opts.height,
+            opts.bit_depth,
+            opts.alpha,
+            opts.transfer,
+        ),
+        OutputFormat::Pfm => hdr::write_pfm(&opts.output, &screen, opts.width, opts.height),
+        OutputFormat::Hdr => hdr::write_rgbe(&opts.output, &screen, opts.width, opts.height),
+        OutputFormat::Exr => exr::write_exr(
+            &opts.output,
+            &screen,
+            opts.width,
+            opts.height,
+            opts.exr_compression,
+            opts.exr_type,
+            opts.alpha,
+        ),
+    };
+    match written {
+        Ok(()) => println!("done writing: {}", opts.output),
+        Err(e) => {
+            eprintln!("error: can't write {}: {}", opts.output, e);
+            process::exit(1);
+        }
+    }
 }
@@ -17,6 +17,20 @@ impl Mat4 {
         return Mat4 { m: m };
     }
 
+    // rotation taking camera space (looking down -z, y up) to world space
+    pub fn look_at(eye: &Vec4, target: &Vec4, up: &Vec4) -> Mat4 {
+        let back: Vec4 = (*eye - *target).normalize();
+        let right: Vec4 = up.cross(back).normalize();
+        let true_up: Vec4 = back.cross(right);
+
+        let mut m = [Vec4::new(0., 0., 0., 0.); 4];
+        m[0] = Vec4::new(right.x, true_up.x, back.x, 0.);
+        m[1] = Vec4::new(right.y, true_up.y, back.y, 0.);
+        m[2] = Vec4::new(right.z, true_up.z, back.z, 0.);
+        m[3] = Vec4::new(0., 0., 0., 1.);
+        return Mat4 { m: m };
+    }
+
     pub fn column(&self, c: usize) -> Vec4 {
         let x = self.m[0].elem(c);
         let y = self.m[1].elem(c);
@@ -45,11 +59,11 @@ impl ops::Mul<Mat4> for Mat4 {
     fn mul(self, other: Mat4) -> Self::Output {
         let mut m = [Vec4::new(0., 0., 0., 0.); 4];
 
-        for i in 0..4 {
-            m[i].x = self.m[i].dot(other.column(0));
-            m[i].y = self.m[i].dot(other.column(1));
-            m[i].z = self.m[i].dot(other.column(2));
-            m[i].w = self.m[i].dot(other.column(3));
+        for (row, s) in m.iter_mut().zip(self.m.iter()) {
+            row.x = s.dot(other.column(0));
+            row.y = s.dot(other.column(1));
+            row.z = s.dot(other.column(2));
+            row.w = s.dot(other.column(3));
         }
 
         return Mat4 { m: m };
@@ -1,18 +1,32 @@
+use crate::mipmap::MipMap;
+use crate::texture::{TexCoord, TextureMap};
 use crate::vec4::Vec4;
 
 // a subset of the .obj/.mtl with pbr extension
 // essentially just whatever blender exports
 
+// blender writes Ns = (1 - roughness)^2 * 1000, this undoes that
+const MAX_NS: f64 = 1000.;
+const DEFAULT_IOR: f64 = 1.5;
+
 #[derive(Copy, Clone)]
 pub struct Material {
-    pub ns: f64,      // specular exponent
-    pub ka: Vec4,     // ambient colour
-    pub kd: Vec4,     // diffuse colour
-    pub ks: Vec4,     // specular colour
-    pub ke: Vec4,     // emmisive colour, TODO with pathtracing
-    pub ni: f64,      // optical density, not implemented
-    pub d: f64,       // dissolve/transparency, not implemented
-    pub illum: usize, // illumination model
+    pub ns: f64,                // specular exponent
+    pub ka: Vec4,               // ambient colour
+    pub kd: Vec4,               // diffuse colour
+    pub ks: Vec4,               // specular colour
+    pub ke: Vec4,               // emmisive colour, triangles with non-zero ke are area lights
+    pub ni: f64,                // optical density, ior of glass materials
+    pub d: f64,                 // dissolve, 1 is opaque
+    pub tf: Vec4,               // transmission filter, glass colour after one unit
+    pub illum: usize,           // illumination model
+    pub roughness: Option<f64>, // pbr roughness (Pr), derived from ns if missing
+    pub metallic: f64,          // pbr metallic (Pm)
+    // texture maps, multiplied with the constants above
+    pub map_kd: Option<TextureMap>,
+    pub map_ks: Option<TextureMap>,
+    pub map_ns: Option<TextureMap>,
+    pub map_d: Option<TextureMap>,
 }
 
 impl Material {
@@ -23,9 +37,63 @@ impl Material {
             kd: Vec4::new(0.8, 0.8, 0.8, 1.),
             ks: Vec4::new(0.5, 0.5, 0.5, 1.),
             ke: Vec4::new(0., 0., 0., 0.),
-            ni: 0.,
-            d: 0.,
+            ni: 1.,
+            d: 1.,
+            tf: Vec4::new(1., 1., 1., 0.),
             illum: 2,
+            roughness: None,
+            metallic: 0.,
+            map_kd: None,
+            map_ks: None,
+            map_ns: None,
+            map_d: None,
+        }
+    }
+
+    pub fn textured(&self) -> bool {
+        return self.map_kd.is_some()
+            || self.map_ks.is_some()
+            || self.map_ns.is_some()
+            || self.map_d.is_some();
+    }
+
+    // the material at texture coordinate tc, with the maps applied
+    pub fn at(&self, tc: &TexCoord, textures: &[MipMap]) -> Material {
+        let mut mat = *self;
+        if let Some(m) = self.map_kd {
+            mat.kd = self.kd * m.sample(textures, tc);
+        }
+        if let Some(m) = self.map_ks {
+            mat.ks = self.ks * m.sample(textures, tc);
+        }
+        if let Some(m) = self.map_ns {
+            mat.ns = self.ns * m.sample_luminance(textures, tc);
+        }
+        if let Some(m) = self.map_d {
+            mat.d = self.d * m.sample_alpha(textures, tc);
+        }
+        return mat;
+    }
+
+    pub fn ior(&self) -> f64 {
+        // mtl files tend to leave Ni at 0 or 1 for non glass, fall back to
+        // regular glass rather than an interface that does nothing
+        if self.ni <= 1. {
+            return DEFAULT_IOR;
+        }
+        return self.ni;
+    }
+
+    // beer-lambert absorption coefficient inside the material
+    pub fn absorption(&self) -> Vec4 {
+        let c = |t: f64| -(t.clamp(1e-6, 1.)).ln();
+        return Vec4::new(c(self.tf.x), c(self.tf.y), c(self.tf.z), 0.);
+    }
+
+    pub fn roughness(&self) -> f64 {
+        match self.roughness {
+            Some(r) => r.clamp(0., 1.),
+            None => 1. - (self.ns.clamp(0., MAX_NS) / MAX_NS).sqrt(),
         }
     }
 }
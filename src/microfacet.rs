@@ -0,0 +1,153 @@
+use crate::vec4::Vec4;
+
+use std::f64::consts::PI;
+
+// trowbridge-reitz (ggx) distribution with height correlated smith shadowing
+// https://jcgt.org/published/0003/02/03/paper.pdf
+// directions are in the local shading frame, normal = +z
+
+const MIN_ALPHA: f64 = 0.001; // below this the lobe degenerates into a spike
+
+#[derive(Copy, Clone)]
+pub struct Ggx {
+    pub alpha: f64,
+}
+
+impl Ggx {
+    pub fn new(roughness: f64) -> Ggx {
+        // perceptual roughness, squared like most pbr pipelines
+        let r = roughness.clamp(0., 1.);
+        return Ggx {
+            alpha: f64::max(r * r, MIN_ALPHA),
+        };
+    }
+
+    pub fn d(&self, h: &Vec4) -> f64 {
+        if h.z <= 0. {
+            return 0.;
+        }
+        let a2 = self.alpha * self.alpha;
+        let k = h.z * h.z * (a2 - 1.) + 1.;
+        return a2 / (PI * k * k);
+    }
+
+    pub fn lambda(&self, w: &Vec4) -> f64 {
+        let cos2 = w.z * w.z;
+        if cos2 <= 0. {
+            return f64::INFINITY;
+        }
+        let tan2 = f64::max(1. - cos2, 0.) / cos2;
+        return ((1. + self.alpha * self.alpha * tan2).sqrt() - 1.) * 0.5;
+    }
+
+    pub fn g1(&self, w: &Vec4) -> f64 {
+        return 1. / (1. + self.lambda(w));
+    }
+
+    pub fn g(&self, wo: &Vec4, wi: &Vec4) -> f64 {
+        return 1. / (1. + self.lambda(wo) + self.lambda(wi));
+    }
+
+    pub fn sample_h(&self, wo: &Vec4, u1: f64, u2: f64) -> Vec4 {
+        // sample the distribution of normals visible from wo
+        // https://jcgt.org/published/0007/04/01/paper.pdf
+        let vh = Vec4::new(self.alpha * wo.x, self.alpha * wo.y, wo.z, 0.).normalize();
+
+        let lensq = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if lensq > 0. {
+            Vec4::new(-vh.y, vh.x, 0., 0.) / lensq.sqrt()
+        } else {
+            Vec4::new(1., 0., 0., 0.)
+        };
+        let t2 = vh.cross(t1);
+
+        let r = u1.sqrt();
+        let phi = 2. * PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1. + vh.z);
+        let p2 = (1. - s) * (1. - p1 * p1).sqrt() + s * r * phi.sin();
+
+        let nh = t1 * p1 + t2 * p2 + vh * f64::max(0., 1. - p1 * p1 - p2 * p2).sqrt();
+        return Vec4::new(
+            self.alpha * nh.x,
+            self.alpha * nh.y,
+            f64::max(nh.z, 1e-6),
+            0.,
+        )
+        .normalize();
+    }
+
+    // pdf of sample_h returning h
+    pub fn pdf_h(&self, wo: &Vec4, h: &Vec4) -> f64 {
+        if wo.z <= 0. {
+            return 0.;
+        }
+        return self.g1(wo) * f64::max(wo.dot(*h), 0.) * self.d(h) / wo.z;
+    }
+}
+
+pub fn schlick(f0: Vec4, cos: f64) -> Vec4 {
+    let m = (1. - cos.clamp(0., 1.)).powi(5);
+    return f0 + (Vec4::new(1., 1., 1., 0.) - f0) * m;
+}
+
+pub fn reflect(w: &Vec4, n: &Vec4) -> Vec4 {
+    // mirror w about n, both pointing away from the surface
+    return *n * (2. * w.dot(*n)) - *w;
+}
+
+// unpolarized fresnel reflectance of a dielectric interface, eta is the
+// relative ior n_t / n_i and cos_i the cosine on the incident side
+pub fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let cos_i = cos_i.clamp(0., 1.);
+    let sin2_t = (1. - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1. {
+        return 1.; // total internal reflection
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    return (rs * rs + rp * rp) * 0.5;
+}
+
+// refract w (pointing away from the surface, same side as n) through an
+// interface with relative ior eta, None on total internal reflection
+pub fn refract(w: &Vec4, n: &Vec4, eta: f64) -> Option<Vec4> {
+    let cos_i = n.dot(*w);
+    let sin2_t = f64::max(0., 1. - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1. {
+        return None;
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    return Some(*w * (-1. / eta) + *n * (cos_i / eta - cos_t));
+}
+
+// fresnel reflectance of a conductor with complex ior eta + ik
+pub fn fresnel_conductor(cos_i: f64, eta: f64, k: f64) -> f64 {
+    // https://seblagarde.wordpress.com/2013/04/29/memo-on-fresnel-equations/
+    let c2 = cos_i.clamp(0., 1.).powi(2);
+    let s2 = 1. - c2;
+
+    let t0 = eta * eta - k * k - s2;
+    let a2b2 = (t0 * t0 + 4. * eta * eta * k * k).sqrt();
+    let t1 = a2b2 + c2;
+    let a = (0.5 * (a2b2 + t0)).max(0.).sqrt();
+    let t2 = 2. * cos_i.clamp(0., 1.) * a;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = c2 * a2b2 + s2 * s2;
+    let t4 = t2 * s2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+
+    return 0.5 * (rp + rs);
+}
+
+// complex ior giving reflectance r at normal incidence with a white edge tint
+// https://jcgt.org/published/0003/04/03/paper.pdf
+pub fn conductor_ior(r: f64) -> (f64, f64) {
+    let r = r.clamp(0., 0.99);
+    let n = (1. - r) / (1. + r);
+    let k2 = (r * (n + 1.) * (n + 1.) - (n - 1.) * (n - 1.)) / (1. - r);
+    return (n, k2.max(0.).sqrt());
+}
@@ -0,0 +1,247 @@
+use crate::image::Image;
+use crate::vec4::Vec4;
+
+use std::str::FromStr;
+
+// image pyramids for filtered texture lookups, after pbrt's MIPMap
+// https://pbr-book.org/3ed-2018/Texture/Image_Texture
+
+// how stretched the ellipse of an ewa lookup may get, longer footprints are
+// widened, blurring a little instead of looping over ever more texels
+const MAX_ANISOTROPY: f64 = 8.;
+// falloff of the gaussian ewa weights
+const EWA_ALPHA: f64 = 2.;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum TextureFilter {
+    Bilinear, // full resolution only, aliases when minified
+    #[default]
+    Trilinear, // isotropic, blurs surfaces seen at grazing angles
+    Ewa,      // elliptical weighted average, anisotropic
+}
+
+impl FromStr for TextureFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TextureFilter, String> {
+        match s {
+            "bilinear" | "none" => Ok(TextureFilter::Bilinear),
+            "trilinear" => Ok(TextureFilter::Trilinear),
+            "ewa" | "anisotropic" => Ok(TextureFilter::Ewa),
+            _ => Err(format!("unknown texture filter: {}", s)),
+        }
+    }
+}
+
+pub struct MipMap {
+    pub levels: Vec<Image>, // full resolution first, down to 1x1
+    pub alpha: bool,
+}
+
+impl MipMap {
+    pub fn new(image: Image) -> MipMap {
+        let alpha = image.alpha;
+        let mut levels: Vec<Image> = vec![image];
+        loop {
+            let prev = levels.last().unwrap();
+            if prev.width == 1 && prev.height == 1 {
+                break;
+            }
+            let next = downsample(prev);
+            levels.push(next);
+        }
+        return MipMap {
+            levels: levels,
+            alpha: alpha,
+        };
+    }
+
+    // st is in 0..1 across the image, t down from the top row. clamp sticks
+    // to the edge outside that, otherwise the image repeats
+    pub fn lookup(
+        &self,
+        filter: TextureFilter,
+        st: (f64, f64),
+        dst0: (f64, f64),
+        dst1: (f64, f64),
+        clamp: bool,
+    ) -> Vec4 {
+        match filter {
+            TextureFilter::Bilinear => self.bilinear(0, st, clamp),
+            TextureFilter::Trilinear => {
+                let width = 2.
+                    * [dst0.0, dst0.1, dst1.0, dst1.1]
+                        .iter()
+                        .fold(0., |m: f64, d| m.max(d.abs()));
+                self.trilinear(st, width, clamp)
+            }
+            TextureFilter::Ewa => self.ewa(st, dst0, dst1, clamp),
+        }
+    }
+
+    // fractional level at which a filter width (in st units) covers about
+    // one texel
+    fn level(&self, width: f64) -> f64 {
+        let res = usize::max(self.levels[0].width, self.levels[0].height) as f64;
+        return (width * res).max(1e-8).log2();
+    }
+
+    fn texel(&self, level: usize, x: i64, y: i64, clamp: bool) -> Vec4 {
+        let img = &self.levels[level];
+        let (w, h) = (img.width as i64, img.height as i64);
+        let (x, y) = if clamp {
+            (x.clamp(0, w - 1), y.clamp(0, h - 1))
+        } else {
+            (x.rem_euclid(w), y.rem_euclid(h))
+        };
+        return img.texel(x as usize, y as usize);
+    }
+
+    fn bilinear(&self, level: usize, st: (f64, f64), clamp: bool) -> Vec4 {
+        let img = &self.levels[level];
+        // texel centres sit at half integers
+        let x = st.0 * img.width as f64 - 0.5;
+        let y = st.1 * img.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = self.texel(level, x0, y0, clamp) * (1. - fx)
+            + self.texel(level, x0 + 1, y0, clamp) * fx;
+        let bottom = self.texel(level, x0, y0 + 1, clamp) * (1. - fx)
+            + self.texel(level, x0 + 1, y0 + 1, clamp) * fx;
+        return top * (1. - fy) + bottom * fy;
+    }
+
+    // blend of the two levels around the one where width spans a texel
+    fn trilinear(&self, st: (f64, f64), width: f64, clamp: bool) -> Vec4 {
+        let top = self.levels.len() - 1;
+        let level = self.level(width);
+        if level <= 0. {
+            return self.bilinear(0, st, clamp);
+        }
+        if level >= top as f64 {
+            return self.texel(top, 0, 0, clamp);
+        }
+        let i = level.floor() as usize;
+        let d = level - i as f64;
+        return self.bilinear(i, st, clamp) * (1. - d) + self.bilinear(i + 1, st, clamp) * d;
+    }
+
+    // gaussian weighted average over the ellipse with axes dst0 and dst1,
+    // on the level where the minor axis spans a few texels
+    fn ewa(&self, st: (f64, f64), dst0: (f64, f64), dst1: (f64, f64), clamp: bool) -> Vec4 {
+        let len = |d: (f64, f64)| (d.0 * d.0 + d.1 * d.1).sqrt();
+        let (mut major, mut minor) = (dst0, dst1);
+        if len(major) < len(minor) {
+            std::mem::swap(&mut major, &mut minor);
+        }
+        let longer = len(major);
+        let mut shorter = len(minor);
+        if shorter * MAX_ANISOTROPY < longer && shorter > 0. {
+            let scale = longer / (shorter * MAX_ANISOTROPY);
+            minor = (minor.0 * scale, minor.1 * scale);
+            shorter *= scale;
+        }
+        if shorter == 0. {
+            return self.bilinear(0, st, clamp);
+        }
+
+        let level = self.level(shorter).max(0.);
+        let i = level.floor() as usize;
+        let d = level - i as f64;
+        return self.ewa_level(i, st, major, minor, clamp) * (1. - d)
+            + self.ewa_level(i + 1, st, major, minor, clamp) * d;
+    }
+
+    fn ewa_level(
+        &self,
+        level: usize,
+        st: (f64, f64),
+        dst0: (f64, f64),
+        dst1: (f64, f64),
+        clamp: bool,
+    ) -> Vec4 {
+        if level >= self.levels.len() {
+            return self.texel(self.levels.len() - 1, 0, 0, clamp);
+        }
+        let img = &self.levels[level];
+        let (w, h) = (img.width as f64, img.height as f64);
+        let s = st.0 * w - 0.5;
+        let t = st.1 * h - 0.5;
+        let (ds0, dt0) = (dst0.0 * w, dst0.1 * h);
+        let (ds1, dt1) = (dst1.0 * w, dst1.1 * h);
+
+        // implicit ellipse a s^2 + b s t + c t^2 = 1, grown by a texel so
+        // it never falls between texel centres
+        let mut a = dt0 * dt0 + dt1 * dt1 + 1.;
+        let mut b = -2. * (ds0 * dt0 + ds1 * dt1);
+        let mut c = ds0 * ds0 + ds1 * ds1 + 1.;
+        let inv_f = 1. / (a * c - b * b * 0.25);
+        a *= inv_f;
+        b *= inv_f;
+        c *= inv_f;
+
+        // bounding box of the ellipse
+        let det = -b * b + 4. * a * c;
+        let inv_det = 1. / det;
+        let u_sqrt = (det * c).sqrt();
+        let v_sqrt = (a * det).sqrt();
+        let s0 = (s - 2. * inv_det * u_sqrt).ceil() as i64;
+        let s1 = (s + 2. * inv_det * u_sqrt).floor() as i64;
+        let t0 = (t - 2. * inv_det * v_sqrt).ceil() as i64;
+        let t1 = (t + 2. * inv_det * v_sqrt).floor() as i64;
+
+        let mut sum = Vec4::new(0., 0., 0., 0.);
+        let mut weights = 0.;
+        for it in t0..=t1 {
+            let tt = it as f64 - t;
+            for is in s0..=s1 {
+                let ss = is as f64 - s;
+                let r2 = a * ss * ss + b * ss * tt + c * tt * tt;
+                if r2 < 1. {
+                    let weight = (-EWA_ALPHA * r2).exp() - (-EWA_ALPHA).exp();
+                    sum += self.texel(level, is, it, clamp) * weight;
+                    weights += weight;
+                }
+            }
+        }
+        if weights <= 0. {
+            return self.bilinear(level, st, clamp);
+        }
+        return sum / weights;
+    }
+}
+
+// half the size, rounded down but at least 1, each texel the box filtered
+// average of the ones it covers. odd sizes share the middle texels
+fn downsample(img: &Image) -> Image {
+    let width = usize::max(1, img.width / 2);
+    let height = usize::max(1, img.height / 2);
+    let span = |i: usize, from: usize, to: usize| -> (usize, usize) {
+        let start = i * from / to;
+        let end = usize::max(start + 1, ((i + 1) * from).div_ceil(to));
+        return (start, end);
+    };
+
+    let mut pixels: Vec<Vec4> = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let (y0, y1) = span(y, img.height, height);
+        for x in 0..width {
+            let (x0, x1) = span(x, img.width, width);
+            let mut sum = Vec4::new(0., 0., 0., 0.);
+            for sy in y0..y1 {
+                for sx in x0..x1 {
+                    sum += img.texel(sx, sy);
+                }
+            }
+            pixels.push(sum / ((y1 - y0) * (x1 - x0)) as f64);
+        }
+    }
+    return Image {
+        width: width,
+        height: height,
+        pixels: pixels,
+        alpha: img.alpha,
+    };
+}
@@ -1,267 +1,693 @@
 use crate::aabb::AABB;
-use crate::kdtree::KDNode;
+use crate::accel::{self, Accel, AccelKind};
+use crate::emitters::Emitters;
+use crate::load::{LoadError, LoadOptions, Tokens};
 use crate::mat4::Mat4;
 use crate::material::Material;
+use crate::mipmap::MipMap;
+use crate::polygon;
+use crate::texture::{TextureCache, TextureMap};
 use crate::triangle::Triangle;
 use crate::vec4::{NVec4, Vec4};
-use std::sync::{Arc, Mutex};
 
-use std::cmp;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::path::Path;
 
 pub struct Obj {
-    pub vertices: Vec<NVec4>,
-    pub head: KDNode,
+    pub triangles: Vec<Triangle>,
+    pub materials: Vec<Material>,
+    pub accel: Box<dyn Accel>,
     pub aabb: AABB,
+    pub emitters: Emitters,
+    pub textures: Vec<MipMap>, // materials' maps point into this
+    // lines skipped in lenient mode, and in either mode ones that loaded
+    // but look off (non-planar faces, statements that aren't supported)
+    pub warnings: Vec<LoadError>,
 }
 
 impl Obj {
-    pub fn from_file(objpath: &str, m: &Mat4) -> Obj {
-        let (vertices, triangles, aabb) = read_obj(objpath, m);
+    pub fn from_file(
+        objpath: &str,
+        m: &Mat4,
+        accel_kind: AccelKind,
+        options: &LoadOptions,
+    ) -> Result<Obj, LoadError> {
+        let mut warnings: Vec<LoadError> = Vec::new();
+        let (vertices, triangles, materials, textures, aabb) =
+            read_obj(objpath, m, options, &mut warnings)?;
 
         println!(
-            "read: {} verts, {} triangles",
+            "read: {} verts, {} triangles, {} textures",
             vertices.len(),
-            triangles.len()
+            triangles.len(),
+            textures.len()
         );
 
-        let head = KDNode::new(&triangles, 0, &aabb);
+        let accel = accel::build(accel_kind, &triangles, &aabb);
+        println!("{}", accel.summary());
+        let emitters = Emitters::new(&triangles, &materials);
 
-        return Obj {
-            vertices: vertices,
-            head: head,
+        return Ok(Obj {
+            triangles: triangles,
+            materials: materials,
+            accel: accel,
             aabb: aabb,
-        };
+            emitters: emitters,
+            textures: textures,
+            warnings: warnings,
+        });
+    }
+
+    // the material at p on t, with its texture maps looked up. dp is the
+    // footprint of a pixel around p, textures are filtered over it
+    pub fn material_at(&self, t: &Triangle, p: &Vec4, dp: Option<(Vec4, Vec4)>) -> Material {
+        let mat = &self.materials[t.mat];
+        if !mat.textured() {
+            return *mat;
+        }
+        return mat.at(&t.tex_coord(p, dp), &self.textures);
     }
 }
 
-pub fn read_obj(objpath: &str, m: &Mat4) -> (Vec<NVec4>, Vec<Triangle>, AABB) {
-    let mut materials: HashMap<String, Material> = HashMap::new();
-    let mut cur_material: Option<Material> = Some(Material::default());
-    let obj_contents: String = fs::read_to_string(objpath).expect("couldn't open obj");
-    let mut vertices: Vec<NVec4> = Vec::new();
-    let mut itriangles: Vec<(usize, usize, usize, Option<Material>)> = Vec::new();
-    let mut triangles: Vec<Triangle> = Vec::new();
+type ObjContents = (Vec<NVec4>, Vec<Triangle>, Vec<Material>, Vec<MipMap>, AABB);
 
-    let mut minV = Vec4::new(0., 0., 0., 0.);
-    let mut maxV = Vec4::new(0., 0., 0., 0.);
+pub fn read_obj(
+    objpath: &str,
+    m: &Mat4,
+    options: &LoadOptions,
+    warnings: &mut Vec<LoadError>,
+) -> Result<ObjContents, LoadError> {
+    let obj_contents: String = fs::read_to_string(objpath)
+        .map_err(|e| LoadError::file(objpath, format!("can't read obj: {}", e)))?;
 
-    for line in obj_contents.split("\n") {
-        if line == "" {
-            continue;
-        }
-        let mut sl = line.split_whitespace();
+    let mut reader = ObjReader {
+        dir: Path::new(objpath).parent().unwrap_or(Path::new("")),
+        m: m,
+        options: options,
+        warnings: warnings,
+        // triangles refer to materials by index, 0 is the default material
+        materials: vec![Material::default()],
+        textures: TextureCache::default(),
+        material_names: HashMap::new(),
+        cur_material: 0,
+        unknown: HashSet::new(),
+        vertices: Vec::new(),
+        texcoords: Vec::new(),
+        normals: Vec::new(),
+        faces: Vec::new(),
+        min_v: Vec4::new(0., 0., 0., 0.),
+        max_v: Vec4::new(0., 0., 0., 0.),
+    };
+
+    for (line_num, line) in obj_contents.lines().enumerate() {
+        let mut t = Tokens::new(objpath, line_num + 1, line);
+        let first = match t.next_word() {
+            Some(w) if !w.starts_with('#') => w,
+            _ => continue,
+        };
 
-        let fsl = sl.next();
-        if fsl.is_none() {
-            continue;
+        if let Err(e) = reader.read_line(&mut t, first) {
+            reader.skip(e)?;
         }
+    }
+
+    let mut vertices = reader.vertices;
+    let texcoords = reader.texcoords;
+    let normals = reader.normals;
+    let mut triangles: Vec<Triangle> = Vec::new();
+
+    for v in &mut vertices {
+        v.n = v.n.normalize();
+    }
+    for (c, mat) in &reader.faces {
+        let corner = |k: &Corner| -> (NVec4, Vec4) {
+            let p = NVec4 {
+                v: vertices[k.v].v,
+                n: k.vn.map_or(vertices[k.v].n, |i| normals[i]),
+            };
+            let t = k.vt.map_or(Vec4::new(0., 0., 0., 0.), |i| texcoords[i]);
+            return (p, t);
+        };
+        let (p0, t0) = corner(&c[0]);
+        let (p1, t1) = corner(&c[1]);
+        let (p2, t2) = corner(&c[2]);
+        triangles.push(Triangle {
+            p0: p0,
+            p1: p1,
+            p2: p2,
+            t0: t0,
+            t1: t1,
+            t2: t2,
+            mat: *mat,
+        });
+    }
+    let aabb = AABB {
+        min: reader.min_v,
+        max: reader.max_v,
+    };
 
-        let first = fsl.unwrap();
-        if first.chars().next().unwrap() == '#' {
-            continue;
+    return Ok((
+        vertices,
+        triangles,
+        reader.materials,
+        reader.textures.images,
+        aabb,
+    ));
+}
+
+// everything read from an obj so far
+struct ObjReader<'a> {
+    dir: &'a Path, // the obj's, for files it mentions
+    m: &'a Mat4,
+    options: &'a LoadOptions,
+    warnings: &'a mut Vec<LoadError>,
+    materials: Vec<Material>,
+    textures: TextureCache,
+    material_names: HashMap<String, usize>,
+    cur_material: usize,
+    unknown: HashSet<String>, // statements already warned about
+    vertices: Vec<NVec4>,
+    texcoords: Vec<Vec4>,
+    normals: Vec<Vec4>,
+    faces: Vec<([Corner; 3], usize)>,
+    min_v: Vec4,
+    max_v: Vec4,
+}
+
+impl ObjReader<'_> {
+    // lenient loads carry on past errors, keeping them as warnings
+    fn skip(&mut self, e: LoadError) -> Result<(), LoadError> {
+        if !self.options.lenient {
+            return Err(e);
         }
+        self.warnings.push(e);
+        return Ok(());
+    }
 
+    // one statement, nothing is kept of a line that fails
+    fn read_line(&mut self, t: &mut Tokens, first: &str) -> Result<(), LoadError> {
         match first {
             "v" => {
                 // vertex
-                let x: f64 = sl.next().unwrap().parse::<f64>().unwrap();
-                let y: f64 = sl.next().unwrap().parse::<f64>().unwrap();
-                let z: f64 = sl.next().unwrap().parse::<f64>().unwrap();
-                let mut nv: Vec4 = Vec4::new(x, y, z, 1.);
-                nv = (*m) * nv;
-                let nnv: NVec4 = NVec4 {
+                let x: f64 = t.parse("x coordinate")?;
+                let y: f64 = t.parse("y coordinate")?;
+                let z: f64 = t.parse("z coordinate")?;
+                let nv: Vec4 = (*self.m) * Vec4::new(x, y, z, 1.);
+                self.vertices.push(NVec4 {
                     v: nv,
                     n: Vec4::new(0., 0., 0., 0.),
-                };
-
-                vertices.push(nnv);
+                });
 
-                minV.x = f64::min(minV.x, nv.x);
-                minV.y = f64::min(minV.y, nv.y);
-                minV.z = f64::min(minV.z, nv.z);
+                self.min_v.x = f64::min(self.min_v.x, nv.x);
+                self.min_v.y = f64::min(self.min_v.y, nv.y);
+                self.min_v.z = f64::min(self.min_v.z, nv.z);
 
-                maxV.x = f64::max(maxV.x, nv.x);
-                maxV.y = f64::max(maxV.y, nv.y);
-                maxV.z = f64::max(maxV.z, nv.z);
+                self.max_v.x = f64::max(self.max_v.x, nv.x);
+                self.max_v.y = f64::max(self.max_v.y, nv.y);
+                self.max_v.z = f64::max(self.max_v.z, nv.z);
+            }
+            "vt" => {
+                // texture coordinate, v and w are optional
+                let u: f64 = t.parse("u coordinate")?;
+                let v: f64 = t.parse_opt("v coordinate")?.unwrap_or(0.);
+                self.texcoords.push(Vec4::new(u, v, 0., 0.));
+            }
+            "vn" => {
+                // vertex normal, m is only ever a rotation or a uniform
+                // scale so it can transform normals as is
+                let x: f64 = t.parse("normal x")?;
+                let y: f64 = t.parse("normal y")?;
+                let z: f64 = t.parse("normal z")?;
+                self.normals
+                    .push(((*self.m) * Vec4::new(x, y, z, 0.)).normalize());
             }
             "f" => {
                 // face
-                let mut v: Vec<usize> = Vec::new();
-                for s in sl {
-                    let mut vspl = s.split("/");
-                    v.push(vspl.next().unwrap().parse::<usize>().unwrap());
-                    // TODO handle textures and maybe normals
+                let mut c: Vec<Corner> = Vec::new();
+                while let Some(s) = t.next_word() {
+                    let corner = parse_corner(
+                        s,
+                        self.vertices.len(),
+                        self.texcoords.len(),
+                        self.normals.len(),
+                    );
+                    c.push(corner.map_err(|e| t.error(e))?);
+                }
+                if c.len() < 3 {
+                    return Err(
+                        t.line_error(format!("face needs at least 3 corners, has {}", c.len()))
+                    );
                 }
 
-                let mut i0: usize = v[0];
-                let mut i1: usize = v[1];
-                let mut i2: usize = v[2];
-
-                // obj use 1-based indexing, have to modify
-                // TODO: support negative indices
-                i0 -= 1;
-                i1 -= 1;
-                i2 -= 1;
-
-                let p0: NVec4 = vertices[i0];
-                let p1: NVec4 = vertices[i1];
-                let p2: NVec4 = vertices[i2];
-
-                let nt: Triangle = Triangle {
-                    p0: p0,
-                    p1: p1,
-                    p2: p2,
-                    mat: None,
-                };
+                let points: Vec<Vec4> = c.iter().map(|k| self.vertices[k.v].v).collect();
+                let tri = polygon::triangulate(&points);
+                // a warning in strict mode too: the face is valid obj and
+                // still loads, only its shading may look off. lenient mode
+                // is about skipping lines that can't be read at all
+                if !tri.planar {
+                    self.warnings
+                        .push(t.line_error("face isn't planar".to_string()));
+                }
 
-                let n: Vec4 = nt.normal();
+                for [i0, i1, i2] in tri.triangles {
+                    let f: [Corner; 3] = [c[i0], c[i1], c[i2]];
 
-                vertices[i0].n += n;
-                vertices[i1].n += n;
-                vertices[i2].n += n;
+                    // face normals are summed up per vertex, for corners
+                    // that don't come with their own normal
+                    let a: Vec4 = self.vertices[f[1].v].v - self.vertices[f[0].v].v;
+                    let b: Vec4 = self.vertices[f[2].v].v - self.vertices[f[0].v].v;
+                    let n: Vec4 = a.cross(b).normalize();
+                    for k in &f {
+                        self.vertices[k.v].n += n;
+                    }
 
-                itriangles.push((i0, i1, i2, cur_material));
-                //println!("added triangle: {} {} {}", p0, p1, p2);
+                    self.faces.push((f, self.cur_material));
+                }
             }
             "o" => {
                 // mayb extend this in the future, this *should* be fine for now
-                cur_material = Some(Material::default());
+                self.cur_material = 0;
             }
             "mtllib" => {
-                materials.extend(read_mtl(sl.next().unwrap()));
-            }
-            "usemtl" => match materials.get(sl.next().unwrap()) {
-                Some(x) => {
-                    cur_material = Some(*x);
+                // any number of files, so one missing doesn't lose the rest
+                for mtlpath in self.options.find_files(t, self.dir, "mtl file") {
+                    let read = mtlpath.and_then(|p| {
+                        read_mtl(
+                            &p,
+                            self.dir,
+                            self.options,
+                            &mut self.textures,
+                            self.warnings,
+                        )
+                    });
+                    match read {
+                        Ok(mats) => {
+                            for (name, mat) in mats {
+                                self.material_names.insert(name, self.materials.len());
+                                self.materials.push(mat);
+                            }
+                        }
+                        Err(e) => self.skip(e)?,
+                    }
                 }
-                None => {
-                    panic!("couldnt find material");
+            }
+            "usemtl" => {
+                let name = t.expect("material name")?;
+                match self.material_names.get(name) {
+                    Some(x) => {
+                        self.cur_material = *x;
+                    }
+                    None => {
+                        // lenient loads go on with the default material
+                        self.cur_material = 0;
+                        return Err(t.error(format!("unknown material: {}", name)));
+                    }
                 }
-            },
-            // TODO: vertex normals, materials, textures...
+            }
+            // valid obj the renderer has no use for: groups, smoothing,
+            // lines, points and the free-form geometry statements
+            "g" | "s" | "l" | "p" | "mg" | "lod" | "usemap" | "maplib" | "bevel" | "c_interp"
+            | "d_interp" | "shadow_obj" | "trace_obj" | "ctech" | "stech" | "vp" | "cstype"
+            | "deg" | "bmat" | "step" | "curv" | "curv2" | "surf" | "parm" | "trim" | "hole"
+            | "scrv" | "sp" | "end" | "con" => {}
             _ => {
-                println!("read unknown obj token: {}", first);
+                // once per keyword, not once per line
+                if self.unknown.insert(first.to_string()) {
+                    self.warnings
+                        .push(t.error(format!("unknown obj statement: {}", first)));
+                }
             }
         }
+        return Ok(());
     }
+}
 
-    for v in &mut vertices {
-        v.n = v.n.normalize();
+// one corner of a face, indices into the vertex, texture coordinate and
+// normal lists
+#[derive(Copy, Clone)]
+struct Corner {
+    v: usize,
+    vt: Option<usize>,
+    vn: Option<usize>,
+}
+
+// v, v/vt, v//vn or v/vt/vn
+fn parse_corner(s: &str, num_v: usize, num_vt: usize, num_vn: usize) -> Result<Corner, String> {
+    let mut parts = s.split('/');
+    let v = resolve_index(parts.next().unwrap_or(""), num_v, "vertex")?;
+    let vt = match parts.next() {
+        Some(x) if !x.is_empty() => Some(resolve_index(x, num_vt, "texture coordinate")?),
+        _ => None,
+    };
+    let vn = match parts.next() {
+        Some(x) if !x.is_empty() => Some(resolve_index(x, num_vn, "normal")?),
+        _ => None,
+    };
+    return Ok(Corner {
+        v: v,
+        vt: vt,
+        vn: vn,
+    });
+}
+
+// obj indices start at 1, negative ones count back from the last element
+// read so far
+fn resolve_index(s: &str, count: usize, what: &str) -> Result<usize, String> {
+    let i: i64 = s
+        .parse::<i64>()
+        .map_err(|_| format!("invalid {} index: {}", what, s))?;
+    let idx: i64 = if i > 0 { i - 1 } else { count as i64 + i };
+    if i == 0 || idx < 0 || idx >= count as i64 {
+        return Err(format!(
+            "{} index {} out of range, {} defined",
+            what, s, count
+        ));
     }
-    for i in &itriangles {
-        let t = Triangle {
-            p0: vertices[(*i).0],
-            p1: vertices[(*i).1],
-            p2: vertices[(*i).2],
-            mat: (*i).3,
+    return Ok(idx as usize);
+}
+
+pub fn read_mtl(
+    mtlpath: &Path,
+    obj_dir: &Path,
+    options: &LoadOptions,
+    textures: &mut TextureCache,
+    warnings: &mut Vec<LoadError>,
+) -> Result<HashMap<String, Material>, LoadError> {
+    let mut reader = MtlReader {
+        dir: mtlpath.parent().unwrap_or(Path::new("")),
+        obj_dir: obj_dir,
+        options: options,
+        textures: textures,
+        warnings: warnings,
+        material_stack: Vec::new(),
+    };
+    let mtlpath: &str = &mtlpath.to_string_lossy();
+    let mtl_contents: String = fs::read_to_string(mtlpath)
+        .map_err(|e| LoadError::file(mtlpath, format!("can't read mtl: {}", e)))?;
+
+    for (line_num, line) in mtl_contents.lines().enumerate() {
+        let mut t = Tokens::new(mtlpath, line_num + 1, line);
+        let first = match t.next_word() {
+            Some(w) if !w.starts_with('#') => w,
+            _ => continue,
         };
-        triangles.push(t);
+
+        if let Err(e) = reader.read_line(&mut t, first) {
+            if !options.lenient {
+                return Err(e);
+            }
+            reader.warnings.push(e);
+        }
+    }
+
+    let mut materials: HashMap<String, Material> = HashMap::new();
+    for (s, mat) in reader.material_stack {
+        println!("{}: {} {} {}", s, mat.ka, mat.kd, mat.ks);
+        materials.insert(s.clone(), mat);
     }
-    let aabb = AABB {
-        min: minV,
-        max: maxV,
-    };
 
-    return (vertices, triangles, aabb);
+    return Ok(materials);
 }
 
-pub fn read_mtl(mtlpath: &str) -> HashMap<String, Material> {
-    let mut material_stack: Vec<(String, Material)> = Vec::new();
-    let mtl_contents: String = fs::read_to_string(mtlpath).expect("couldn't open mtl");
+// materials read from an mtl so far
+struct MtlReader<'a> {
+    dir: &'a Path, // the mtl's, textures are looked for here first
+    obj_dir: &'a Path,
+    options: &'a LoadOptions,
+    textures: &'a mut TextureCache,
+    warnings: &'a mut Vec<LoadError>,
+    material_stack: Vec<(String, Material)>,
+}
 
-    for line in mtl_contents.split("\n") {
-        if line == "" {
-            continue;
-        }
-        let mut sl = line.split_whitespace();
-        let fsl = sl.next();
-        if fsl.is_none() {
-            continue;
+impl MtlReader<'_> {
+    fn read_line(&mut self, t: &mut Tokens, first: &str) -> Result<(), LoadError> {
+        if first == "newmtl" {
+            self.material_stack.push((
+                t.expect("material name")?.to_string(),
+                (Material {
+                    ns: 0.,
+                    ka: Vec4::new(0., 0., 0., 0.),
+                    kd: Vec4::new(0., 0., 0., 0.),
+                    ks: Vec4::new(0., 0., 0., 0.),
+                    ke: Vec4::new(0., 0., 0., 0.),
+                    ni: 1.,
+                    d: 1.,
+                    tf: Vec4::new(1., 1., 1., 0.),
+                    illum: 2,
+                    roughness: None,
+                    metallic: 0.,
+                    map_kd: None,
+                    map_ks: None,
+                    map_ns: None,
+                    map_d: None,
+                }),
+            ));
+            println!("created material {}", self.material_stack.last().unwrap().0);
+            return Ok(());
         }
-        let first = fsl.unwrap();
-        if first.chars().next().unwrap() == '#' {
-            continue;
+
+        if self.material_stack.is_empty() {
+            return Err(t.error(format!("{} before any newmtl", first)));
         }
 
+        // maps are read before borrowing the material, they need self
+        let map = match first {
+            "map_Kd" | "map_Ks" => Some(self.read_map(t, true)?),
+            "map_Ns" | "map_d" => Some(self.read_map(t, false)?),
+            _ => None,
+        };
+
+        let mat: &mut Material = &mut self.material_stack.last_mut().unwrap().1;
         match first {
-            "newmtl" => {
-                material_stack.push((
-                    sl.next().unwrap().to_string(),
-                    (Material {
-                        ns: 0.,
-                        ka: Vec4::new(0., 0., 0., 0.),
-                        kd: Vec4::new(0., 0., 0., 0.),
-                        ks: Vec4::new(0., 0., 0., 0.),
-                        ke: Vec4::new(0., 0., 0., 0.),
-                        ni: 0.,
-                        d: 0.,
-                        illum: 2,
-                    }),
-                ));
-                println!("created material {}", material_stack.last().unwrap().0);
-            }
-            "Ns" => {
-                let ns: f64 = sl.next().unwrap().parse::<f64>().unwrap();
-                material_stack.last_mut().unwrap().1.ns = ns;
-            }
-            "Ka" => {
-                let r = sl.next().unwrap().parse::<f64>().unwrap();
-                let g = sl.next().unwrap().parse::<f64>().unwrap();
-                let b = sl.next().unwrap().parse::<f64>().unwrap();
-                material_stack.last_mut().unwrap().1.ka.x = r;
-                material_stack.last_mut().unwrap().1.ka.y = g;
-                material_stack.last_mut().unwrap().1.ka.z = b;
+            "Ns" => mat.ns = t.parse("specular exponent")?,
+            "Ka" => mat.ka = parse_rgb(t)?,
+            "Kd" => mat.kd = parse_rgb(t)?,
+            "Ks" => mat.ks = parse_rgb(t)?,
+            "Ke" => mat.ke = parse_rgb(t)?,
+            "Tf" => {
+                // a single value means grey
+                let r: f64 = t.parse("red")?;
+                let g: f64 = t.parse_opt("green")?.unwrap_or(r);
+                let b: f64 = t.parse_opt("blue")?.unwrap_or(r);
+                mat.tf = Vec4::new(r, g, b, 0.);
             }
-            "Kd" => {
-                let r = sl.next().unwrap().parse::<f64>().unwrap();
-                let g = sl.next().unwrap().parse::<f64>().unwrap();
-                let b = sl.next().unwrap().parse::<f64>().unwrap();
-                material_stack.last_mut().unwrap().1.kd.x = r;
-                material_stack.last_mut().unwrap().1.kd.y = g;
-                material_stack.last_mut().unwrap().1.kd.z = b;
+            "Ni" => mat.ni = t.parse("index of refraction")?,
+            "d" => mat.d = t.parse("dissolve")?,
+            "Tr" => {
+                // some exporters write transparency instead of dissolve
+                let tr: f64 = t.parse("transparency")?;
+                mat.d = 1. - tr;
             }
-            "Ks" => {
-                let r = sl.next().unwrap().parse::<f64>().unwrap();
-                let g = sl.next().unwrap().parse::<f64>().unwrap();
-                let b = sl.next().unwrap().parse::<f64>().unwrap();
-                material_stack.last_mut().unwrap().1.ks.x = r;
-                material_stack.last_mut().unwrap().1.ks.y = g;
-                material_stack.last_mut().unwrap().1.ks.z = b;
-            }
-            "Ke" => {
-                let r = sl.next().unwrap().parse::<f64>().unwrap();
-                let g = sl.next().unwrap().parse::<f64>().unwrap();
-                let b = sl.next().unwrap().parse::<f64>().unwrap();
-                material_stack.last_mut().unwrap().1.ke.x = r;
-                material_stack.last_mut().unwrap().1.ke.y = g;
-                material_stack.last_mut().unwrap().1.ke.z = b;
+            "Pr" => mat.roughness = Some(t.parse("roughness")?),
+            "Pm" => mat.metallic = t.parse("metallic")?,
+            "illum" => mat.illum = t.parse("illumination model")?,
+            "map_Kd" => mat.map_kd = map,
+            "map_Ks" => mat.map_ks = map,
+            "map_Ns" => mat.map_ns = map,
+            "map_d" => mat.map_d = map,
+            _ => {
+                self.warnings
+                    .push(t.error(format!("unknown mtl statement: {}", first)));
             }
+        }
+        return Ok(());
+    }
 
-            "Ni" => {
-                let ni: f64 = sl.next().unwrap().parse::<f64>().unwrap();
-                material_stack.last_mut().unwrap().1.ni = ni;
-            }
-            "d" => {
-                let d: f64 = sl.next().unwrap().parse::<f64>().unwrap();
-                material_stack.last_mut().unwrap().1.d = d;
-            }
-            "illum" => {
-                let illum: usize = sl.next().unwrap().parse::<usize>().unwrap();
-                material_stack.last_mut().unwrap().1.illum = illum;
+    // [options] file, srgb for colour maps
+    fn read_map(&mut self, t: &mut Tokens, srgb: bool) -> Result<TextureMap, LoadError> {
+        let mut map = TextureMap::new(0);
+        map.filter = self.options.texture_filter;
+        while let Some(opt) = t.peek().filter(|w| w.starts_with('-')) {
+            t.next_word();
+            match opt {
+                "-s" => map.scale = parse_uvw(t, 1.)?,
+                "-o" => map.offset = parse_uvw(t, 0.)?,
+                // not in the mtl spec, overrides --texture-filter for one map
+                "-filter" => {
+                    let name = t.expect("texture filter")?;
+                    map.filter = name.parse().map_err(|e: String| t.error(e))?;
+                }
+                "-clamp" => {
+                    map.clamp = match t.expect("on or off")? {
+                        "on" => true,
+                        "off" => false,
+                        x => return Err(t.error(format!("-clamp takes on or off, got: {}", x))),
+                    }
+                }
+                // valid, but nothing here uses them
+                "-blendu" | "-blendv" | "-cc" | "-bm" | "-boost" | "-texres" | "-imfchan"
+                | "-mm" | "-t" => {
+                    let column = t.column();
+                    let args = match opt {
+                        "-mm" => 2,
+                        "-t" => 0, // counted below, one to three numbers
+                        _ => 1,
+                    };
+                    for _ in 0..args {
+                        t.expect(&format!("argument of {}", opt))?;
+                    }
+                    if opt == "-t" {
+                        parse_uvw(t, 0.)?;
+                    }
+                    self.warnings.push(t.error_at(
+                        column,
+                        format!("texture option {} isn't supported, ignored", opt),
+                    ));
+                }
+                _ => return Err(t.error(format!("unknown texture option: {}", opt))),
             }
-            _ => {
-                println!("read unknown mtl token: {}", first);
+        }
+
+        let name = t.rest();
+        if name.is_empty() {
+            return Err(t.error("expected texture file name".to_string()));
+        }
+        let path = self
+            .options
+            .find(name, self.dir)
+            .or_else(|| self.options.find(name, self.obj_dir))
+            .ok_or_else(|| t.error(format!("can't find texture: {}", name)))?;
+        map.image = self
+            .textures
+            .load(&path, srgb)
+            .map_err(|e| t.error(format!("can't read texture {}: {}", path.display(), e)))?;
+        return Ok(map);
+    }
+}
+
+// u [v [w]], what's left out keeps its default
+fn parse_uvw(t: &mut Tokens, default: f64) -> Result<Vec4, LoadError> {
+    let mut uvw = Vec4::new(default, default, default, 0.);
+    uvw.x = t.parse("u")?;
+    for i in 1..3 {
+        match t.peek().map(|w| w.parse::<f64>()) {
+            Some(Ok(v)) => {
+                t.next_word();
+                uvw.set_elem(i, v);
             }
+            _ => break,
         }
     }
+    return Ok(uvw);
+}
 
-    let mut materials: HashMap<String, Material> = HashMap::new();
-    for (s, mat) in material_stack {
-        println!("{}: {} {} {}", s, mat.ka, mat.kd, mat.ks);
-        materials.insert(s.clone(), mat);
+// r g b, all three required
+fn parse_rgb(t: &mut Tokens) -> Result<Vec4, LoadError> {
+    let r: f64 = t.parse("red")?;
+    let g: f64 = t.parse("green")?;
+    let b: f64 = t.parse("blue")?;
+    return Ok(Vec4::new(r, g, b, 0.));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // writes src to a fresh file under the temp dir and reads it back
+    fn read_str(name: &str, src: &str, options: &LoadOptions) -> Result<ObjContents, LoadError> {
+        return read_str_warnings(name, src, options, &mut Vec::new());
+    }
+
+    fn read_str_warnings(
+        name: &str,
+        src: &str,
+        options: &LoadOptions,
+        warnings: &mut Vec<LoadError>,
+    ) -> Result<ObjContents, LoadError> {
+        let dir = std::env::temp_dir().join(format!("rustpt-obj-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, src).unwrap();
+        return read_obj(path.to_str().unwrap(), &Mat4::identity(), options, warnings);
     }
 
-    return materials;
+    #[test]
+    fn positive_and_negative_indices() {
+        assert_eq!(resolve_index("1", 3, "vertex"), Ok(0));
+        assert_eq!(resolve_index("3", 3, "vertex"), Ok(2));
+        assert_eq!(resolve_index("-1", 3, "vertex"), Ok(2));
+        assert_eq!(resolve_index("-3", 3, "vertex"), Ok(0));
+    }
+
+    #[test]
+    fn out_of_range_indices() {
+        assert!(resolve_index("0", 3, "vertex").is_err());
+        assert!(resolve_index("4", 3, "vertex").is_err());
+        assert!(resolve_index("-4", 3, "vertex").is_err());
+        assert!(resolve_index("1", 0, "normal").is_err());
+        assert_eq!(
+            resolve_index("x", 3, "vertex"),
+            Err("invalid vertex index: x".to_string())
+        );
+    }
+
+    #[test]
+    fn corner_forms() {
+        let c = parse_corner("2", 3, 3, 3).unwrap();
+        assert_eq!((c.v, c.vt, c.vn), (1, None, None));
+        let c = parse_corner("2/-1", 3, 3, 3).unwrap();
+        assert_eq!((c.v, c.vt, c.vn), (1, Some(2), None));
+        let c = parse_corner("-2//1", 3, 3, 3).unwrap();
+        assert_eq!((c.v, c.vt, c.vn), (1, None, Some(0)));
+        let c = parse_corner("1/2/3", 3, 3, 3).unwrap();
+        assert_eq!((c.v, c.vt, c.vn), (0, Some(1), Some(2)));
+        assert!(parse_corner("1/4/1", 3, 3, 3).is_err());
+        assert!(parse_corner("1/1/4", 3, 3, 3).is_err());
+    }
+
+    #[test]
+    fn negative_indices_are_relative_to_the_line() {
+        // the second face's -3 -2 -1 names the vertices after the first face
+        let src = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\n\
+                   v 0 0 1\nv 1 0 1\nv 0 1 1\nf -3 -2 -1\n";
+        let (_, triangles, _, _, _) =
+            read_str("relative.obj", src, &LoadOptions::default()).unwrap();
+        assert_eq!(triangles.len(), 2);
+        assert_eq!(triangles[0].p0.v.z, 0.);
+        assert_eq!(triangles[1].p0.v.z, 1.);
+        assert_eq!(triangles[1].p2.v.y, 1.);
+    }
+
+    #[test]
+    fn out_of_range_face_reports_its_location() {
+        let src = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n";
+        let e = read_str("range.obj", src, &LoadOptions::default())
+            .err()
+            .unwrap();
+        assert_eq!((e.line, e.column), (4, 7));
+        assert!(e.reason.contains("out of range"), "{}", e.reason);
+
+        // lenient mode drops the face and carries on
+        let options = LoadOptions {
+            lenient: true,
+            ..LoadOptions::default()
+        };
+        let (_, triangles, _, _, _) = read_str("range.obj", src, &options).unwrap();
+        assert!(triangles.is_empty());
+    }
+
+    #[test]
+    fn non_planar_face_warns_in_strict_mode() {
+        let src = "v 0 0 0\nv 1 0 0\nv 1 1 1\nv 0 1 0\nf 1 2 3 4\n";
+        let mut warnings: Vec<LoadError> = Vec::new();
+        let (_, triangles, _, _, _) =
+            read_str_warnings("bent.obj", src, &LoadOptions::default(), &mut warnings).unwrap();
+        assert_eq!(triangles.len(), 2);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].line, 5);
+        assert!(warnings[0].reason.contains("planar"));
+    }
+
+    #[test]
+    fn unknown_statements_warn_once() {
+        let src = "g cube\ns 1\nv 0 0 0\nv 1 0 0\nv 0 1 0\nl 1 2\nfoo 1\nfoo 2\nf 1 2 3\n";
+        let mut warnings: Vec<LoadError> = Vec::new();
+        let (_, triangles, _, _, _) =
+            read_str_warnings("odd.obj", src, &LoadOptions::default(), &mut warnings).unwrap();
+        assert_eq!(triangles.len(), 1);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].line, 7);
+        assert!(warnings[0].reason.contains("foo"));
+    }
 }
@@ -0,0 +1,493 @@
+use crate::image::{self, Image};
+use crate::tonemap::Transfer;
+use crate::vec4::Vec4;
+use crate::zlib;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+// png writer, rgb or rgba at 8 or 16 bits per channel. takes display values
+// that have been through the tone mapper already. the reader takes every
+// colour type, bit depth and interlacing png has
+// https://www.w3.org/TR/png/
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+pub fn write_png(
+    path: &str,
+    screen: &[Vec4],
+    res_x: usize,
+    res_y: usize,
+    bit_depth: u8,
+    alpha: bool,
+    transfer: Transfer,
+) -> io::Result<()> {
+    let channels = if alpha { 4 } else { 3 };
+    let bytes_per_px = channels * bit_depth as usize / 8;
+    let stride = res_x * bytes_per_px;
+
+    // raw scanlines, big endian samples
+    let mut raw: Vec<u8> = Vec::with_capacity(stride * res_y);
+    for px in screen.iter().take(res_x * res_y) {
+        let c: [f64; 4] = [px.x, px.y, px.z, px.w];
+        for v in c.iter().take(channels) {
+            let v = v.clamp(0., 1.);
+            if bit_depth == 16 {
+                raw.extend(((v * 65535.).round() as u16).to_be_bytes());
+            } else {
+                raw.push((v * 255.).round() as u8);
+            }
+        }
+    }
+
+    let mut filtered: Vec<u8> = Vec::with_capacity((stride + 1) * res_y);
+    let zero: Vec<u8> = vec![0; stride];
+    for y in 0..res_y {
+        let row = &raw[y * stride..(y + 1) * stride];
+        let up = if y > 0 {
+            &raw[(y - 1) * stride..y * stride]
+        } else {
+            &zero[..]
+        };
+        filter_row(row, up, bytes_per_px, &mut filtered);
+    }
+
+    let mut ihdr: Vec<u8> = Vec::with_capacity(13);
+    ihdr.extend((res_x as u32).to_be_bytes());
+    ihdr.extend((res_y as u32).to_be_bytes());
+    ihdr.push(bit_depth);
+    ihdr.push(if alpha { 6 } else { 2 }); // truecolour with or without alpha
+    ihdr.extend([0, 0, 0]); // deflate, adaptive filtering, not interlaced
+
+    let mut buf = BufWriter::new(File::create(path)?);
+    buf.write_all(&SIGNATURE)?;
+    write_chunk(&mut buf, b"IHDR", &ihdr)?;
+    if transfer == Transfer::Srgb {
+        write_chunk(&mut buf, b"sRGB", &[0])?; // perceptual rendering intent
+    }
+    // decoders without srgb support fall back to this, 1/2.2 either way
+    write_chunk(&mut buf, b"gAMA", &45455u32.to_be_bytes())?;
+    write_chunk(&mut buf, b"IDAT", &zlib::compress(&filtered))?;
+    write_chunk(&mut buf, b"IEND", &[])?;
+    return buf.flush();
+}
+
+// appends the row with whichever filter gives the smallest sum of absolute
+// differences, the usual heuristic for what deflate handles best
+fn filter_row(row: &[u8], up: &[u8], bpp: usize, out: &mut Vec<u8>) {
+    let mut best: Vec<u8> = Vec::new();
+    let mut best_cost = u64::MAX;
+    let mut best_type = 0;
+    let mut candidate: Vec<u8> = vec![0; row.len()];
+
+    for filter in 0..5u8 {
+        let mut cost: u64 = 0;
+        for i in 0..row.len() {
+            let a = if i >= bpp { row[i - bpp] } else { 0 };
+            let b = up[i];
+            let c = if i >= bpp { up[i - bpp] } else { 0 };
+            let predicted = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                _ => paeth(a, b, c),
+            };
+            let v = row[i].wrapping_sub(predicted);
+            candidate[i] = v;
+            cost += (v as i8).unsigned_abs() as u64;
+        }
+        if cost < best_cost {
+            best_cost = cost;
+            best_type = filter;
+            std::mem::swap(&mut best, &mut candidate);
+            candidate.resize(row.len(), 0);
+        }
+    }
+    out.push(best_type);
+    out.extend(best);
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        return a;
+    }
+    if pb <= pc {
+        return b;
+    }
+    return c;
+}
+
+// top left corner and spacing of the pixels in each adam7 pass
+const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+pub fn read_png(data: &[u8]) -> Result<Image, String> {
+    if !data.starts_with(&SIGNATURE) {
+        return Err("not a png".to_string());
+    }
+
+    let mut header: Option<Header> = None;
+    let mut palette: Vec<[u8; 3]> = Vec::new();
+    let mut trns: Vec<u8> = Vec::new();
+    let mut idat: Vec<u8> = Vec::new();
+
+    let mut pos = SIGNATURE.len();
+    loop {
+        let len = be32(data, pos)? as usize;
+        let kind = data.get(pos + 4..pos + 8).ok_or("truncated chunk")?;
+        let body = data.get(pos + 8..pos + 8 + len).ok_or("truncated chunk")?;
+        let mut crc = Crc32::new();
+        crc.update(kind);
+        crc.update(body);
+        if crc.finish() != be32(data, pos + 8 + len)? {
+            return Err(format!(
+                "bad crc in {} chunk",
+                String::from_utf8_lossy(kind)
+            ));
+        }
+        pos += 12 + len;
+
+        match kind {
+            b"IHDR" => header = Some(Header::new(body)?),
+            b"PLTE" => palette = body.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect(),
+            b"tRNS" => trns = body.to_vec(),
+            b"IDAT" => idat.extend(body),
+            b"IEND" => break,
+            _ => {
+                // ancillary chunks have a lower case first letter and can be
+                // skipped, anything else is needed to decode the image
+                if kind[0] & 0x20 == 0 {
+                    return Err(format!(
+                        "unknown critical chunk {}",
+                        String::from_utf8_lossy(kind)
+                    ));
+                }
+            }
+        }
+    }
+
+    let h = header.ok_or("missing IHDR")?;
+    if h.color_type == 3 && palette.is_empty() {
+        return Err("missing palette".to_string());
+    }
+    let passes: &[(usize, usize, usize, usize)] = if h.interlaced {
+        &ADAM7
+    } else {
+        &[(0, 0, 1, 1)]
+    };
+
+    // size of every pass's filtered rows, a filter byte ahead of each, known
+    // from the header before anything is inflated
+    let mut expected = 0usize;
+    for &(x0, y0, dx, dy) in passes {
+        let w = (h.width + dx - 1 - x0) / dx;
+        let rows = (h.height + dy - 1 - y0) / dy;
+        if w == 0 || rows == 0 {
+            continue;
+        }
+        let stride = w
+            .checked_mul(h.bits_per_pixel())
+            .map(|bits| bits.div_ceil(8))
+            .ok_or("image too large")?;
+        expected = (stride + 1)
+            .checked_mul(rows)
+            .and_then(|size| size.checked_add(expected))
+            .ok_or("image too large")?;
+    }
+    let raw = zlib::decompress(&idat, expected)?;
+    if raw.len() < expected {
+        return Err("truncated image data".to_string());
+    }
+
+    let mut pixels = vec![Vec4::new(0., 0., 0., 1.); h.width * h.height];
+    let mut pos = 0;
+    for &(x0, y0, dx, dy) in passes {
+        let w = (h.width + dx - 1 - x0) / dx;
+        let rows = (h.height + dy - 1 - y0) / dy;
+        if w == 0 || rows == 0 {
+            continue;
+        }
+        let stride = (w * h.bits_per_pixel()).div_ceil(8);
+        let size = (stride + 1) * rows;
+        let pass = &raw[pos..pos + size];
+        pos += size;
+
+        let lines = unfilter(pass, stride, rows, h.bits_per_pixel().div_ceil(8))?;
+        for (j, line) in lines.chunks(stride).enumerate() {
+            for i in 0..w {
+                let px = h.pixel(line, i, &palette, &trns);
+                pixels[(y0 + j * dy) * h.width + x0 + i * dx] = px;
+            }
+        }
+    }
+
+    return Ok(Image {
+        width: h.width,
+        height: h.height,
+        pixels: pixels,
+        alpha: h.color_type == 4 || h.color_type == 6 || !trns.is_empty(),
+    });
+}
+
+struct Header {
+    width: usize,
+    height: usize,
+    bit_depth: usize,
+    color_type: u8,
+    interlaced: bool,
+}
+
+impl Header {
+    fn new(body: &[u8]) -> Result<Header, String> {
+        if body.len() != 13 {
+            return Err("invalid IHDR".to_string());
+        }
+        let h = Header {
+            width: be32(body, 0)? as usize,
+            height: be32(body, 4)? as usize,
+            bit_depth: body[8] as usize,
+            color_type: body[9],
+            interlaced: body[12] == 1,
+        };
+        let depth_ok = match h.color_type {
+            0 => matches!(h.bit_depth, 1 | 2 | 4 | 8 | 16),
+            3 => matches!(h.bit_depth, 1 | 2 | 4 | 8),
+            2 | 4 | 6 => matches!(h.bit_depth, 8 | 16),
+            _ => return Err(format!("invalid colour type {}", h.color_type)),
+        };
+        if !depth_ok || h.width == 0 || h.height == 0 || body[10] != 0 || body[11] != 0 {
+            return Err("invalid IHDR".to_string());
+        }
+        if body[12] > 1 {
+            return Err("unknown interlace method".to_string());
+        }
+        image::checked_size(h.width, h.height)?;
+        return Ok(h);
+    }
+
+    fn channels(&self) -> usize {
+        match self.color_type {
+            0 | 3 => 1,
+            4 => 2,
+            2 => 3,
+            _ => 4,
+        }
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        return self.channels() * self.bit_depth;
+    }
+
+    // sample k of an unfiltered line, packed most significant bits first
+    // below 8 bits
+    fn sample(&self, line: &[u8], k: usize) -> u16 {
+        match self.bit_depth {
+            16 => (line[2 * k] as u16) << 8 | line[2 * k + 1] as u16,
+            8 => line[k] as u16,
+            d => {
+                let bit = k * d;
+                let shift = 8 - d - bit % 8;
+                ((line[bit / 8] >> shift) & ((1 << d) - 1) as u8) as u16
+            }
+        }
+    }
+
+    fn pixel(&self, line: &[u8], i: usize, palette: &[[u8; 3]], trns: &[u8]) -> Vec4 {
+        let n = self.channels();
+        let max = ((1u32 << self.bit_depth) - 1) as f64;
+        let s: Vec<u16> = (0..n).map(|c| self.sample(line, i * n + c)).collect();
+        let f = |v: u16| v as f64 / max;
+
+        // for grey and rgb images trns holds the one colour that's see
+        // through, as 16 bit values whatever the depth
+        let key = |c: usize| -> u16 { (trns[2 * c] as u16) << 8 | trns[2 * c + 1] as u16 };
+        match self.color_type {
+            0 => {
+                let a = if trns.len() >= 2 && s[0] == key(0) {
+                    0.
+                } else {
+                    1.
+                };
+                Vec4::new(f(s[0]), f(s[0]), f(s[0]), a)
+            }
+            2 => {
+                let a = if trns.len() >= 6 && (0..3).all(|c| s[c] == key(c)) {
+                    0.
+                } else {
+                    1.
+                };
+                Vec4::new(f(s[0]), f(s[1]), f(s[2]), a)
+            }
+            3 => {
+                let idx = s[0] as usize;
+                let c = palette.get(idx).copied().unwrap_or([0, 0, 0]);
+                let a = trns.get(idx).copied().unwrap_or(255);
+                Vec4::new(
+                    c[0] as f64 / 255.,
+                    c[1] as f64 / 255.,
+                    c[2] as f64 / 255.,
+                    a as f64 / 255.,
+                )
+            }
+            4 => Vec4::new(f(s[0]), f(s[0]), f(s[0]), f(s[1])),
+            _ => Vec4::new(f(s[0]), f(s[1]), f(s[2]), f(s[3])),
+        }
+    }
+}
+
+// undoes the per row filters, the inverse of filter_row
+fn unfilter(data: &[u8], stride: usize, rows: usize, bpp: usize) -> Result<Vec<u8>, String> {
+    let mut out: Vec<u8> = vec![0; stride * rows];
+    for y in 0..rows {
+        let filter = data[y * (stride + 1)];
+        let line = &data[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        let (done, rest) = out.split_at_mut(y * stride);
+        let up: &[u8] = if y > 0 {
+            &done[(y - 1) * stride..]
+        } else {
+            &[]
+        };
+        let cur = &mut rest[..stride];
+        for i in 0..stride {
+            let a = if i >= bpp { cur[i - bpp] } else { 0 };
+            let b = if y > 0 { up[i] } else { 0 };
+            let c = if i >= bpp && y > 0 { up[i - bpp] } else { 0 };
+            let predicted = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(format!("invalid filter type {}", filter)),
+            };
+            cur[i] = line[i].wrapping_add(predicted);
+        }
+    }
+    return Ok(out);
+}
+
+fn be32(data: &[u8], pos: usize) -> Result<u32, String> {
+    let b = data.get(pos..pos + 4).ok_or("truncated chunk")?;
+    return Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+}
+
+fn write_chunk(w: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    w.write_all(kind)?;
+    w.write_all(data)?;
+    let mut crc = Crc32::new();
+    crc.update(kind);
+    crc.update(data);
+    return w.write_all(&crc.finish().to_be_bytes());
+}
+
+struct Crc32 {
+    table: [u32; 256],
+    crc: u32,
+}
+
+impl Crc32 {
+    fn new() -> Crc32 {
+        let mut table = [0u32; 256];
+        for (n, entry) in table.iter_mut().enumerate() {
+            let mut c = n as u32;
+            for _ in 0..8 {
+                c = if c & 1 != 0 {
+                    0xedb88320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                };
+            }
+            *entry = c;
+        }
+        return Crc32 {
+            table: table,
+            crc: 0xffffffff,
+        };
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for b in data {
+            self.crc = self.table[((self.crc ^ *b as u32) & 0xff) as usize] ^ (self.crc >> 8);
+        }
+    }
+
+    fn finish(&self) -> u32 {
+        return self.crc ^ 0xffffffff;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a png from filtered scanlines, one IDAT
+    fn encode(width: u32, height: u32, depth: u8, colour: u8, filtered: &[u8]) -> Vec<u8> {
+        let mut ihdr: Vec<u8> = Vec::new();
+        ihdr.extend(width.to_be_bytes());
+        ihdr.extend(height.to_be_bytes());
+        ihdr.extend([depth, colour, 0, 0, 0]);
+        let mut out: Vec<u8> = SIGNATURE.to_vec();
+        write_chunk(&mut out, b"IHDR", &ihdr).unwrap();
+        write_chunk(&mut out, b"IDAT", &zlib::compress(filtered)).unwrap();
+        write_chunk(&mut out, b"IEND", &[]).unwrap();
+        return out;
+    }
+
+    #[test]
+    fn reads_small_grey_image() {
+        // two rows, no filter then the up filter
+        let data = encode(2, 2, 8, 0, &[0, 0, 255, 2, 255, 0]);
+        let image = read_png(&data).unwrap();
+        assert_eq!((image.width, image.height, image.alpha), (2, 2, false));
+        let grey: Vec<f64> = image.pixels.iter().map(|p| p.x).collect();
+        assert_eq!(grey, vec![0., 1., 1., 1.]);
+    }
+
+    #[test]
+    fn write_png_round_trips() {
+        let screen: Vec<Vec4> = (0..6)
+            .map(|i| {
+                let v = (40 * i) as f64 / 255.;
+                Vec4::new(v, 1. - v, 0.5 * v, 1. - 0.25 * v)
+            })
+            .collect();
+        let path = std::env::temp_dir().join(format!("rustpt-png-{}.png", std::process::id()));
+        let path = path.to_str().unwrap();
+        write_png(path, &screen, 3, 2, 16, true, Transfer::Srgb).unwrap();
+        let image = read_png(&std::fs::read(path).unwrap()).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!((image.width, image.height, image.alpha), (3, 2, true));
+        for (a, b) in image.pixels.iter().zip(&screen) {
+            assert!((a.x - b.x).abs() < 1e-4 && (a.y - b.y).abs() < 1e-4);
+            assert!((a.z - b.z).abs() < 1e-4 && (a.w - b.w).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn rejects_huge_header() {
+        let data = encode(0x7fffffff, 0x7fffffff, 16, 6, &[0]);
+        assert!(read_png(&data).is_err());
+    }
+
+    #[test]
+    fn rejects_too_much_or_too_little_data() {
+        // a 1x1 grey image is two filtered bytes
+        assert!(read_png(&encode(1, 1, 8, 0, &vec![0; 1 << 20])).is_err());
+        assert!(read_png(&encode(4, 4, 8, 0, &[0; 5])).is_err());
+        assert!(read_png(&encode(1, 1, 8, 0, &[0, 7])).is_ok());
+    }
+}
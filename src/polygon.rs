@@ -0,0 +1,286 @@
+use crate::vec4::Vec4;
+
+// splitting obj faces with more than three corners into triangles
+
+// relative to the size of the polygon, how far a corner may be off the
+// best fit plane before the face counts as non-planar
+const PLANAR_TOLERANCE: f64 = 1e-3;
+
+pub struct Triangulation {
+    pub triangles: Vec<[usize; 3]>, // corner indices, in the winding of the face
+    pub planar: bool,
+}
+
+// fans out convex polygons, clips ears off concave ones. non-planar faces
+// are triangulated in their projection onto the best fit plane
+pub fn triangulate(points: &[Vec4]) -> Triangulation {
+    let n = points.len();
+    if n <= 3 {
+        return Triangulation {
+            triangles: if n == 3 { vec![[0, 1, 2]] } else { Vec::new() },
+            planar: true,
+        };
+    }
+
+    // newell's method, robust to concave corners and a bit of noise
+    let mut normal = Vec4::new(0., 0., 0., 0.);
+    let mut centroid = Vec4::new(0., 0., 0., 0.);
+    for i in 0..n {
+        let a = points[i];
+        let b = points[(i + 1) % n];
+        normal.x += (a.y - b.y) * (a.z + b.z);
+        normal.y += (a.z - b.z) * (a.x + b.x);
+        normal.z += (a.x - b.x) * (a.y + b.y);
+        centroid += a;
+    }
+    centroid /= n as f64;
+    if normal.length() == 0. {
+        // no area at all, nothing better to do than a fan
+        return Triangulation {
+            triangles: fan(n),
+            planar: true,
+        };
+    }
+    let normal = normal.normalize();
+
+    let size = points
+        .iter()
+        .map(|p| (*p - centroid).length())
+        .fold(0., f64::max);
+    let planar = points
+        .iter()
+        .all(|p| (*p - centroid).dot(normal).abs() <= PLANAR_TOLERANCE * size);
+
+    // drop the axis the polygon faces most, the 2d winding then follows
+    // the sign of the normal along it
+    let axis = if normal.x.abs() > normal.y.abs() && normal.x.abs() > normal.z.abs() {
+        0
+    } else if normal.y.abs() > normal.z.abs() {
+        1
+    } else {
+        2
+    };
+    let flip = normal.elem(axis) < 0.;
+    let projected: Vec<(f64, f64)> = points
+        .iter()
+        .map(|p| {
+            let (u, v) = (p.elem((axis + 1) % 3), p.elem((axis + 2) % 3));
+            if flip {
+                (v, u)
+            } else {
+                (u, v)
+            }
+        })
+        .collect();
+
+    let convex = (0..n).all(|i| {
+        cross(
+            projected[(i + n - 1) % n],
+            projected[i],
+            projected[(i + 1) % n],
+        ) >= 0.
+    });
+    let triangles = if convex { fan(n) } else { ear_clip(&projected) };
+    return Triangulation {
+        triangles: triangles,
+        planar: planar,
+    };
+}
+
+fn fan(n: usize) -> Vec<[usize; 3]> {
+    return (1..n - 1).map(|i| [0, i, i + 1]).collect();
+}
+
+// z of (b - a) x (c - b), positive for a left turn
+fn cross(a: (f64, f64), b: (f64, f64), c: (f64, f64)) -> f64 {
+    return (b.0 - a.0) * (c.1 - b.1) - (b.1 - a.1) * (c.0 - b.0);
+}
+
+fn inside_triangle(p: (f64, f64), a: (f64, f64), b: (f64, f64), c: (f64, f64)) -> bool {
+    return cross(a, b, p) >= 0. && cross(b, c, p) >= 0. && cross(c, a, p) >= 0.;
+}
+
+// o(n^2) ear clipping of a counter clockwise simple polygon
+// https://www.geometrictools.com/Documentation/TriangulationByEarClipping.pdf
+fn ear_clip(p: &[(f64, f64)]) -> Vec<[usize; 3]> {
+    let mut remaining: Vec<usize> = (0..p.len()).collect();
+    let mut triangles: Vec<[usize; 3]> = Vec::new();
+
+    while remaining.len() > 3 {
+        let m = remaining.len();
+        let ear = (0..m).find(|&i| {
+            let a = remaining[(i + m - 1) % m];
+            let b = remaining[i];
+            let c = remaining[(i + 1) % m];
+            if cross(p[a], p[b], p[c]) <= 0. {
+                return false; // reflex or degenerate corner
+            }
+            // no other corner may be inside the ear, corners that coincide
+            // with the ear's own ones don't count
+            return remaining.iter().all(|&k| {
+                k == a
+                    || k == b
+                    || k == c
+                    || p[k] == p[a]
+                    || p[k] == p[b]
+                    || p[k] == p[c]
+                    || !inside_triangle(p[k], p[a], p[b], p[c])
+            });
+        });
+
+        match ear {
+            Some(i) => {
+                triangles.push([
+                    remaining[(i + m - 1) % m],
+                    remaining[i],
+                    remaining[(i + 1) % m],
+                ]);
+                remaining.remove(i);
+            }
+            None => {
+                // self intersecting, fan out whatever is left
+                for i in 1..m - 1 {
+                    triangles.push([remaining[0], remaining[i], remaining[i + 1]]);
+                }
+                return triangles;
+            }
+        }
+    }
+    triangles.push([remaining[0], remaining[1], remaining[2]]);
+    return triangles;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(xyz: &[(f64, f64, f64)]) -> Vec<Vec4> {
+        return xyz.iter().map(|p| Vec4::new(p.0, p.1, p.2, 1.)).collect();
+    }
+
+    // areas of the triangles, signed by whether they face along normal
+    fn areas(p: &[Vec4], t: &Triangulation, normal: Vec4) -> Vec<f64> {
+        return t
+            .triangles
+            .iter()
+            .map(|[a, b, c]| (p[*b] - p[*a]).cross(p[*c] - p[*a]).dot(normal) * 0.5)
+            .collect();
+    }
+
+    fn assert_covers(p: &[Vec4], normal: Vec4, area: f64) {
+        let t = triangulate(p);
+        assert_eq!(t.triangles.len(), p.len() - 2);
+        let a = areas(p, &t, normal);
+        assert!(
+            a.iter().all(|x| *x > 0.),
+            "flipped or empty triangle: {:?}",
+            a
+        );
+        // positive triangles adding up to the polygon can't stick out of it
+        assert!((a.iter().sum::<f64>() - area).abs() < 1e-9, "{:?}", a);
+    }
+
+    // an l with its reflex corner at (1, 1), area 3
+    const L: [(f64, f64, f64); 6] = [
+        (0., 0., 0.),
+        (2., 0., 0.),
+        (2., 1., 0.),
+        (1., 1., 0.),
+        (1., 2., 0.),
+        (0., 2., 0.),
+    ];
+
+    #[test]
+    fn small_faces() {
+        assert!(triangulate(&points(&L[..2])).triangles.is_empty());
+        assert_eq!(triangulate(&points(&L[..3])).triangles, vec![[0, 1, 2]]);
+    }
+
+    #[test]
+    fn convex_quad_is_fanned() {
+        let p = points(&[(0., 0., 0.), (1., 0., 0.), (1., 1., 0.), (0., 1., 0.)]);
+        let t = triangulate(&p);
+        assert!(t.planar);
+        assert_eq!(t.triangles, vec![[0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn collinear_corner_leaves_a_sliver() {
+        let p = points(&[
+            (0., 0., 0.),
+            (0.5, 0., 0.),
+            (1., 0., 0.),
+            (1., 1., 0.),
+            (0., 1., 0.),
+        ]);
+        // the fan keeps the corner, so one triangle is a zero area sliver
+        // that rays never hit, the rest still cover the square
+        let t = triangulate(&p);
+        assert_eq!(t.triangles.len(), 3);
+        let a = areas(&p, &t, Vec4::new(0., 0., 1., 0.));
+        assert!(a.iter().all(|x| *x >= 0.));
+        assert!((a.iter().sum::<f64>() - 1.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn concave_l_is_ear_clipped() {
+        let p = points(&L);
+        assert!(triangulate(&p).planar);
+        assert_covers(&p, Vec4::new(0., 0., 1., 0.), 3.);
+    }
+
+    #[test]
+    fn concave_clockwise_keeps_winding() {
+        let mut l = L.to_vec();
+        l.reverse();
+        assert_covers(&points(&l), Vec4::new(0., 0., -1., 0.), 3.);
+    }
+
+    #[test]
+    fn concave_in_other_planes() {
+        // the l on its side, facing -y and +x, so the projection is flipped
+        // or along another axis
+        let xz: Vec<(f64, f64, f64)> = L.iter().map(|p| (p.0, 5., p.1)).collect();
+        assert_covers(&points(&xz), Vec4::new(0., -1., 0., 0.), 3.);
+        let yz: Vec<(f64, f64, f64)> = L.iter().map(|p| (-3., p.0, p.1)).collect();
+        assert_covers(&points(&yz), Vec4::new(1., 0., 0., 0.), 3.);
+    }
+
+    #[test]
+    fn concave_star() {
+        // five pointed star, every other corner reflex
+        let p: Vec<Vec4> = (0..10)
+            .map(|i| {
+                let a = i as f64 * std::f64::consts::PI / 5.;
+                let r = if i % 2 == 0 { 1. } else { 0.4 };
+                Vec4::new(r * a.cos(), r * a.sin(), 0., 1.)
+            })
+            .collect();
+        let t = triangulate(&p);
+        assert_eq!(t.triangles.len(), 8);
+        let a = areas(&p, &t, Vec4::new(0., 0., 1., 0.));
+        assert!(a.iter().all(|x| *x > 0.));
+        let expected = 10. * 0.5 * 0.4 * (std::f64::consts::PI / 5.).sin();
+        assert!((a.iter().sum::<f64>() - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn non_planar_face_is_flagged() {
+        let p = points(&[(0., 0., 0.), (1., 0., 0.), (1., 1., 0.5), (0., 1., 0.)]);
+        let t = triangulate(&p);
+        assert!(!t.planar);
+        assert_eq!(t.triangles.len(), 2);
+    }
+
+    #[test]
+    fn nearly_planar_face_passes() {
+        let p = points(&[(0., 0., 0.), (1., 0., 0.), (1., 1., 1e-5), (0., 1., 0.)]);
+        assert!(triangulate(&p).planar);
+    }
+
+    #[test]
+    fn self_intersecting_face_still_triangulates() {
+        let p = points(&[(0., 0., 0.), (1., 1., 0.), (1., 0., 0.), (0., 1., 0.)]);
+        assert_eq!(triangulate(&p).triangles.len(), 2);
+    }
+}
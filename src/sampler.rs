@@ -0,0 +1,338 @@
+use crate::rng::Rng;
+
+use std::str::FromStr;
+
+// sample generators for everything the renderer integrates over. a pixel
+// sample asks for its dimensions in the same order every time (pixel
+// position, lens, then light and bsdf samples per bounce), so low
+// discrepancy sequences can spread the samples of one pixel evenly in each
+// of them. every sampler is a pure function of (seed, pixel, sample index,
+// dimension), renders come out the same regardless of thread scheduling
+pub trait Sampler {
+    // start sample `index` of pixel (x, y)
+    fn start_pixel_sample(&mut self, x: usize, y: usize, index: usize);
+
+    fn next_1d(&mut self) -> f64;
+
+    fn next_2d(&mut self) -> (f64, f64);
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SamplerKind {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+impl FromStr for SamplerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SamplerKind, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "independent" | "random" => Ok(SamplerKind::Independent),
+            "stratified" => Ok(SamplerKind::Stratified),
+            "halton" => Ok(SamplerKind::Halton),
+            "sobol" => Ok(SamplerKind::Sobol),
+            _ => Err(format!("unknown sampler: {}", s)),
+        }
+    }
+}
+
+pub fn new_sampler(kind: SamplerKind, spp: usize, seed: u64) -> Box<dyn Sampler> {
+    match kind {
+        SamplerKind::Independent => Box::new(IndependentSampler::new(seed)),
+        SamplerKind::Stratified => Box::new(StratifiedSampler::new(spp, seed)),
+        SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+        SamplerKind::Sobol => Box::new(SobolSampler::new(seed)),
+    }
+}
+
+const ONE_MINUS_EPSILON: f64 = 1. - f64::EPSILON / 2.;
+
+// uniform random numbers from a pcg stream per pixel sample
+pub struct IndependentSampler {
+    seed: u64,
+    rng: Rng,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> IndependentSampler {
+        return IndependentSampler {
+            seed: seed,
+            rng: Rng::new(0, 0),
+        };
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, x: usize, y: usize, index: usize) {
+        self.rng = Rng::new(hash(&[x as u64, y as u64, self.seed]), index as u64);
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        return self.rng.next_f64();
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        return (self.rng.next_f64(), self.rng.next_f64());
+    }
+}
+
+// one jittered sample per stratum, spp strata in 1d and a grid of at least
+// spp cells in 2d. the samples of a pixel visit the strata in a different
+// shuffled order in every dimension so the dimensions stay uncorrelated
+pub struct StratifiedSampler {
+    spp: usize,
+    seed: u64,
+    pixel: u64,
+    index: usize,
+    dim: u64,
+    rng: Rng, // jitter inside the strata
+}
+
+impl StratifiedSampler {
+    pub fn new(spp: usize, seed: u64) -> StratifiedSampler {
+        return StratifiedSampler {
+            spp: spp,
+            seed: seed,
+            pixel: 0,
+            index: 0,
+            dim: 0,
+            rng: Rng::new(0, 0),
+        };
+    }
+
+    fn stratum(&mut self, count: usize) -> usize {
+        let h = hash(&[self.pixel, self.dim, self.seed]);
+        self.dim += 1;
+        return permutation_element(self.index as u32, count as u32, h as u32) as usize;
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, x: usize, y: usize, index: usize) {
+        self.pixel = hash(&[x as u64, y as u64]);
+        self.index = index % self.spp;
+        self.dim = 0;
+        self.rng = Rng::new(hash(&[self.pixel, self.seed]), index as u64);
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        let s = self.stratum(self.spp);
+        return (s as f64 + self.rng.next_f64()) / self.spp as f64;
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        let nx = usize::max((self.spp as f64).sqrt() as usize, 1);
+        let ny = self.spp.div_ceil(nx);
+        let s = self.stratum(nx * ny);
+        return (
+            ((s % nx) as f64 + self.rng.next_f64()) / nx as f64,
+            ((s / nx) as f64 + self.rng.next_f64()) / ny as f64,
+        );
+    }
+}
+
+// the first primes, dimension d of the halton sequence is the radical
+// inverse in base PRIMES[d]
+const PRIMES: [u64; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+// halton sequence per pixel, owen scrambled with a different seed per pixel
+// and dimension. past the last prime the bases repeat, the scrambling keeps
+// those dimensions apart
+pub struct HaltonSampler {
+    seed: u64,
+    pixel: u64,
+    index: u64,
+    dim: u64,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> HaltonSampler {
+        return HaltonSampler {
+            seed: seed,
+            pixel: 0,
+            index: 0,
+            dim: 0,
+        };
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, x: usize, y: usize, index: usize) {
+        self.pixel = hash(&[x as u64, y as u64, self.seed]);
+        self.index = index as u64;
+        self.dim = 0;
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        let base = PRIMES[self.dim as usize % PRIMES.len()];
+        let h = hash(&[self.pixel, self.dim]);
+        self.dim += 1;
+        return owen_scrambled_radical_inverse(base, self.index, h);
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        return (self.next_1d(), self.next_1d());
+    }
+}
+
+// owen scrambled sobol (0,2) sequence in every pair of dimensions, with the
+// sample index shuffled per dimension pair so pairs don't line up
+// https://jcgt.org/published/0009/04/01/paper.pdf
+pub struct SobolSampler {
+    seed: u64,
+    pixel: u64,
+    index: u32,
+    dim: u64,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> SobolSampler {
+        return SobolSampler {
+            seed: seed,
+            pixel: 0,
+            index: 0,
+            dim: 0,
+        };
+    }
+
+    fn next_seed(&mut self) -> u32 {
+        let h = hash(&[self.pixel, self.dim]);
+        self.dim += 1;
+        return h as u32;
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, x: usize, y: usize, index: usize) {
+        self.pixel = hash(&[x as u64, y as u64, self.seed]);
+        self.index = index as u32;
+        self.dim = 0;
+    }
+
+    fn next_1d(&mut self) -> f64 {
+        let seed = self.next_seed();
+        let i = nested_uniform_scramble(self.index, seed);
+        let x = nested_uniform_scramble(i.reverse_bits(), mix32(seed, 1));
+        return to_unit(x);
+    }
+
+    fn next_2d(&mut self) -> (f64, f64) {
+        let seed = self.next_seed();
+        let i = nested_uniform_scramble(self.index, seed);
+        let x = nested_uniform_scramble(i.reverse_bits(), mix32(seed, 1));
+        let y = nested_uniform_scramble(sobol_dim1(i), mix32(seed, 2));
+        return (to_unit(x), to_unit(y));
+    }
+}
+
+// second sobol dimension, primitive polynomial x + 1
+fn sobol_dim1(mut i: u32) -> u32 {
+    let mut v: u32 = 1 << 31;
+    let mut x: u32 = 0;
+    while i != 0 {
+        if i & 1 != 0 {
+            x ^= v;
+        }
+        i >>= 1;
+        v ^= v >> 1;
+    }
+    return x;
+}
+
+fn to_unit(x: u32) -> f64 {
+    return f64::min(x as f64 * (1. / 4294967296.), ONE_MINUS_EPSILON);
+}
+
+// owen scrambling of the bits of x, most significant first
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    return laine_karras(x.reverse_bits(), seed).reverse_bits();
+}
+
+fn laine_karras(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    return x;
+}
+
+fn mix32(seed: u32, v: u64) -> u32 {
+    return hash(&[seed as u64, v]) as u32;
+}
+
+// radical inverse of a in the given base with every digit permuted
+// depending on the digits before it
+fn owen_scrambled_radical_inverse(base: u64, mut a: u64, hash: u64) -> f64 {
+    let inv_base = 1. / base as f64;
+    let mut inv_base_m: f64 = 1.;
+    let mut reversed: u64 = 0;
+    while 1. - (base - 1) as f64 * inv_base_m < 1. {
+        let next = a / base;
+        let digit = a - next * base;
+        let digit_hash = mix_bits(hash ^ reversed);
+        let digit = permutation_element(digit as u32, base as u32, digit_hash as u32) as u64;
+        reversed = reversed * base + digit;
+        inv_base_m *= inv_base;
+        a = next;
+    }
+    return f64::min(reversed as f64 * inv_base_m, ONE_MINUS_EPSILON);
+}
+
+// element i of a random permutation of 0..l picked by p, without building it
+// https://graphics.pixar.com/library/MultiJitteredSampling/paper.pdf
+fn permutation_element(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    return i.wrapping_add(p) % l;
+}
+
+fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5d329728ea185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81dadef4bc2dd44d);
+    v ^= v >> 33;
+    return v;
+}
+
+fn hash(values: &[u64]) -> u64 {
+    let mut h: u64 = 0x9e3779b97f4a7c15;
+    for v in values {
+        h = mix_bits(h ^ v.wrapping_add(0x9e3779b97f4a7c15));
+    }
+    return h;
+}
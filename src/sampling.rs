@@ -0,0 +1,65 @@
+use crate::vec4::Vec4;
+use std::f64::consts::{FRAC_PI_2, FRAC_PI_4};
+
+// orthonormal basis around a normal, used to move directions in and out of
+// a local shading space where n = +z
+#[derive(Copy, Clone)]
+pub struct Frame {
+    pub t: Vec4,
+    pub b: Vec4,
+    pub n: Vec4,
+}
+
+impl Frame {
+    pub fn new(n: Vec4) -> Frame {
+        // https://graphics.pixar.com/library/OrthonormalB/paper.pdf
+        let sign = 1f64.copysign(n.z);
+        let a = -1. / (sign + n.z);
+        let b = n.x * n.y * a;
+        let t = Vec4::new(1. + sign * n.x * n.x * a, sign * b, -sign * n.x, 0.);
+        let bt = Vec4::new(b, sign + n.y * n.y * a, -n.y, 0.);
+        return Frame { t, b: bt, n };
+    }
+
+    pub fn to_world(self, v: Vec4) -> Vec4 {
+        return self.t * v.x + self.b * v.y + self.n * v.z;
+    }
+
+    pub fn to_local(self, v: Vec4) -> Vec4 {
+        return Vec4::new(v.dot(self.t), v.dot(self.b), v.dot(self.n), 0.);
+    }
+}
+
+pub fn concentric_disk(u1: f64, u2: f64) -> (f64, f64) {
+    // shirley-chiu mapping, keeps strata intact unlike the polar mapping
+    let ox = 2. * u1 - 1.;
+    let oy = 2. * u2 - 1.;
+    if ox == 0. && oy == 0. {
+        return (0., 0.);
+    }
+
+    let (r, theta) = if ox.abs() > oy.abs() {
+        (ox, FRAC_PI_4 * (oy / ox))
+    } else {
+        (oy, FRAC_PI_2 - FRAC_PI_4 * (ox / oy))
+    };
+    return (r * theta.cos(), r * theta.sin());
+}
+
+// cosine weighted direction in local space, pdf = cos(theta) / pi
+pub fn cosine_hemisphere(u1: f64, u2: f64) -> Vec4 {
+    let (x, y) = concentric_disk(u1, u2);
+    let z = f64::max(0., 1. - x * x - y * y).sqrt();
+    return Vec4::new(x, y, z, 0.);
+}
+
+// multiple importance sampling weight for a sample drawn from the strategy
+// with pdf a, against one other strategy with pdf b (veach's power heuristic)
+pub fn power_heuristic(a: f64, b: f64) -> f64 {
+    let a2 = a * a;
+    let b2 = b * b;
+    if a2 + b2 <= 0. || a2.is_infinite() {
+        return 1.;
+    }
+    return a2 / (a2 + b2);
+}
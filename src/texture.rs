@@ -0,0 +1,149 @@
+use crate::image::Image;
+use crate::mipmap::{MipMap, TextureFilter};
+use crate::vec4::Vec4;
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+// an mtl map statement: which image, and how uvs are placed on it
+#[derive(Copy, Clone)]
+pub struct TextureMap {
+    pub image: usize,          // index into Obj::textures
+    pub scale: Vec4,           // -s, uvs are scaled...
+    pub offset: Vec4,          // -o, ...then offset
+    pub clamp: bool,           // -clamp, uvs outside 0..1 stick to the edge instead of repeating
+    pub filter: TextureFilter, // -filter, or the default from the load options
+}
+
+// texture coordinates at a shading point, and how they change towards the
+// neighbouring pixels. zero derivatives mean an unfiltered lookup
+pub struct TexCoord {
+    pub uv: Vec4,
+    pub duvdx: Vec4,
+    pub duvdy: Vec4,
+}
+
+impl TextureMap {
+    pub fn new(image: usize) -> TextureMap {
+        return TextureMap {
+            image: image,
+            scale: Vec4::new(1., 1., 1., 0.),
+            offset: Vec4::new(0., 0., 0., 0.),
+            clamp: false,
+            filter: TextureFilter::default(),
+        };
+    }
+
+    // filtered value at tc, v points up the image like in obj
+    pub fn sample(&self, textures: &[MipMap], tc: &TexCoord) -> Vec4 {
+        let u = tc.uv.x * self.scale.x + self.offset.x;
+        let v = tc.uv.y * self.scale.y + self.offset.y;
+        // rows go down the image, so t and its derivatives flip
+        let st = (u, 1. - v);
+        let dst0 = (tc.duvdx.x * self.scale.x, -tc.duvdx.y * self.scale.y);
+        let dst1 = (tc.duvdy.x * self.scale.x, -tc.duvdy.y * self.scale.y);
+        return textures[self.image].lookup(self.filter, st, dst0, dst1, self.clamp);
+    }
+
+    // single channel maps like map_Ns read the colour as grey, whatever
+    // alpha the image has
+    pub fn sample_luminance(&self, textures: &[MipMap], tc: &TexCoord) -> f64 {
+        return self.sample(textures, tc).luminance();
+    }
+
+    // coverage for map_d: the alpha channel when the image has one, grey
+    // masks otherwise
+    pub fn sample_alpha(&self, textures: &[MipMap], tc: &TexCoord) -> f64 {
+        let c = self.sample(textures, tc);
+        if textures[self.image].alpha {
+            return c.w;
+        }
+        return c.luminance();
+    }
+}
+
+// images loaded so far, so materials sharing a file share the image. each
+// is kept with its mip levels, built once here
+#[derive(Default)]
+pub struct TextureCache {
+    pub images: Vec<MipMap>,
+    loaded: HashMap<(PathBuf, bool), usize>,
+}
+
+impl TextureCache {
+    // index of the image at path, colour maps are decoded from srgb and
+    // kept apart from data maps of the same file
+    pub fn load(&mut self, path: &Path, srgb: bool) -> Result<usize, String> {
+        let key = (path.to_path_buf(), srgb);
+        if let Some(i) = self.loaded.get(&key) {
+            return Ok(*i);
+        }
+
+        let mut img = Image::read(path)?;
+        if srgb {
+            img.srgb_to_linear();
+        }
+        self.images.push(MipMap::new(img));
+        self.loaded.insert(key, self.images.len() - 1);
+        return Ok(self.images.len() - 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tex_coord(u: f64, v: f64) -> TexCoord {
+        return TexCoord {
+            uv: Vec4::new(u, v, 0., 0.),
+            duvdx: Vec4::new(0., 0., 0., 0.),
+            duvdy: Vec4::new(0., 0., 0., 0.),
+        };
+    }
+
+    fn single(c: Vec4, alpha: bool) -> Vec<MipMap> {
+        return vec![MipMap::new(Image {
+            width: 1,
+            height: 1,
+            pixels: vec![c],
+            alpha: alpha,
+        })];
+    }
+
+    #[test]
+    fn rgba_maps_give_luminance_for_ns_and_alpha_for_d() {
+        let textures = single(Vec4::new(1., 1., 1., 0.25), true);
+        let map = TextureMap::new(0);
+        let tc = tex_coord(0.5, 0.5);
+        assert!((map.sample_luminance(&textures, &tc) - 1.).abs() < 1e-9);
+        assert!((map.sample_alpha(&textures, &tc) - 0.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn grey_masks_without_alpha() {
+        let textures = single(Vec4::new(0.5, 0.5, 0.5, 1.), false);
+        let map = TextureMap::new(0);
+        let tc = tex_coord(0.5, 0.5);
+        assert!((map.sample_alpha(&textures, &tc) - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn scale_offset_and_wrap() {
+        // two texels, dark on the left, bright on the right
+        let textures = vec![MipMap::new(Image {
+            width: 2,
+            height: 1,
+            pixels: vec![Vec4::new(0., 0., 0., 1.), Vec4::new(1., 1., 1., 1.)],
+            alpha: false,
+        })];
+        let mut map = TextureMap::new(0);
+        map.filter = TextureFilter::Bilinear;
+        assert_eq!(map.sample(&textures, &tex_coord(0.25, 0.5)).x, 0.);
+        assert_eq!(map.sample(&textures, &tex_coord(1.75, 0.5)).x, 1.);
+        map.offset = Vec4::new(0.5, 0., 0., 0.);
+        assert_eq!(map.sample(&textures, &tex_coord(0.25, 0.5)).x, 1.);
+        map.clamp = true;
+        assert_eq!(map.sample(&textures, &tex_coord(5., 0.5)).x, 1.);
+        assert_eq!(map.sample(&textures, &tex_coord(-5., 0.5)).x, 0.);
+    }
+}
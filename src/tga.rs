@@ -0,0 +1,235 @@
+use crate::image::{self, Image};
+use crate::vec4::Vec4;
+
+// truevision tga reader: colour mapped, true colour and greyscale, raw or
+// run length encoded
+// http://www.paulbourke.net/dataformats/tga/
+
+pub fn read_tga(data: &[u8]) -> Result<Image, String> {
+    let h = data.get(0..18).ok_or("truncated header")?;
+    let id_len = h[0] as usize;
+    let map_type = h[1];
+    let image_type = h[2];
+    let map_first = u16::from_le_bytes([h[3], h[4]]) as usize;
+    let map_len = u16::from_le_bytes([h[5], h[6]]) as usize;
+    let map_bits = h[7] as usize;
+    let width = u16::from_le_bytes([h[12], h[13]]) as usize;
+    let height = u16::from_le_bytes([h[14], h[15]]) as usize;
+    let depth = h[16] as usize;
+    let descriptor = h[17];
+
+    let rle = image_type & 8 != 0;
+    let kind = image_type & !8;
+    if !matches!(kind, 1..=3) {
+        return Err(format!("unsupported tga image type {}", image_type));
+    }
+    let count = image::checked_size(width, height)?;
+    let depth_ok = match kind {
+        1 => (depth == 8 || depth == 16) && map_type == 1,
+        2 => matches!(depth, 15 | 16 | 24 | 32),
+        _ => depth == 8 || depth == 16,
+    };
+    if !depth_ok {
+        return Err(format!("unsupported tga pixel depth {}", depth));
+    }
+
+    let mut pos = 18 + id_len;
+    let mut palette: Vec<Vec4> = Vec::new();
+    if map_type == 1 {
+        if !matches!(map_bits, 15 | 16 | 24 | 32) {
+            return Err(format!("unsupported tga colour map depth {}", map_bits));
+        }
+        let entry = map_bits.div_ceil(8);
+        let map = data
+            .get(pos..pos + map_len * entry)
+            .ok_or("truncated colour map")?;
+        palette = map
+            .chunks(entry)
+            .map(|c| true_colour(c, map_bits))
+            .collect();
+        pos += map_len * entry;
+    }
+
+    // every pixel's bytes as stored, runs expanded
+    let size = depth.div_ceil(8);
+    let n = count * size;
+    let rest = data.get(pos..).ok_or("truncated image data")?;
+    let raw: Vec<u8> = if rle {
+        // a packet is at least two bytes for at most 128 pixels
+        if count > rest.len().div_ceil(2) * 128 {
+            return Err("truncated image data".to_string());
+        }
+        let mut raw: Vec<u8> = Vec::with_capacity(n);
+        while raw.len() < n {
+            let packet = *data.get(pos).ok_or("truncated image data")?;
+            pos += 1;
+            let run = (packet & 0x7f) as usize + 1;
+            if packet & 0x80 != 0 {
+                let px = data.get(pos..pos + size).ok_or("truncated image data")?;
+                for _ in 0..run {
+                    raw.extend(px);
+                }
+                pos += size;
+            } else {
+                let px = data
+                    .get(pos..pos + run * size)
+                    .ok_or("truncated image data")?;
+                raw.extend(px);
+                pos += run * size;
+            }
+        }
+        raw.truncate(n);
+        raw
+    } else {
+        rest.get(..n).ok_or("truncated image data")?.to_vec()
+    };
+
+    let mut pixels: Vec<Vec4> = raw
+        .chunks(size)
+        .map(|px| match kind {
+            1 => {
+                let idx = if size == 2 {
+                    u16::from_le_bytes([px[0], px[1]]) as usize
+                } else {
+                    px[0] as usize
+                };
+                palette
+                    .get(idx.wrapping_sub(map_first))
+                    .copied()
+                    .unwrap_or(Vec4::new(0., 0., 0., 1.))
+            }
+            2 => true_colour(px, depth),
+            _ => {
+                let g = px[0] as f64 / 255.;
+                let a = if size == 2 { px[1] as f64 / 255. } else { 1. };
+                Vec4::new(g, g, g, a)
+            }
+        })
+        .collect();
+
+    // rows are stored bottom up unless the descriptor says otherwise, and
+    // very rarely right to left
+    if descriptor & 0x20 == 0 {
+        for y in 0..height / 2 {
+            for x in 0..width {
+                pixels.swap(y * width + x, (height - 1 - y) * width + x);
+            }
+        }
+    }
+    if descriptor & 0x10 != 0 {
+        for row in pixels.chunks_mut(width) {
+            row.reverse();
+        }
+    }
+
+    let alpha = match kind {
+        1 => map_bits == 32,
+        2 => depth == 32,
+        _ => depth == 16,
+    };
+    return Ok(Image {
+        width: width,
+        height: height,
+        pixels: pixels,
+        alpha: alpha,
+    });
+}
+
+// bgr(a) little endian, 15 and 16 bits are 5 bits a channel. the attribute
+// bit of 16 bit pixels is too unreliable to use as alpha
+fn true_colour(px: &[u8], bits: usize) -> Vec4 {
+    match bits {
+        15 | 16 => {
+            let v = u16::from_le_bytes([px[0], px[1]]);
+            let c = |shift: u16| ((v >> shift) & 31) as f64 / 31.;
+            Vec4::new(c(10), c(5), c(0), 1.)
+        }
+        24 => Vec4::new(
+            px[2] as f64 / 255.,
+            px[1] as f64 / 255.,
+            px[0] as f64 / 255.,
+            1.,
+        ),
+        _ => Vec4::new(
+            px[2] as f64 / 255.,
+            px[1] as f64 / 255.,
+            px[0] as f64 / 255.,
+            px[3] as f64 / 255.,
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgba(p: Vec4) -> [f64; 4] {
+        return [p.x, p.y, p.z, p.w];
+    }
+
+    // an 18 byte header with no image id, rows top down
+    fn header(
+        image_type: u8,
+        map: Option<(u16, u8)>,
+        width: u16,
+        height: u16,
+        depth: u8,
+    ) -> Vec<u8> {
+        let (map_len, map_bits) = map.unwrap_or((0, 0));
+        let mut h: Vec<u8> = vec![0, map.is_some() as u8, image_type, 0, 0];
+        h.extend(map_len.to_le_bytes());
+        h.extend([map_bits, 0, 0, 0, 0]);
+        h.extend(width.to_le_bytes());
+        h.extend(height.to_le_bytes());
+        h.extend([depth, 0x20]);
+        return h;
+    }
+
+    #[test]
+    fn reads_true_colour() {
+        let mut data = header(2, None, 2, 1, 24);
+        data.extend([0, 0, 255, 255, 0, 0]); // bgr
+        let image = read_tga(&data).unwrap();
+        assert_eq!((image.width, image.height, image.alpha), (2, 1, false));
+        assert_eq!(rgba(image.pixels[0]), [1., 0., 0., 1.]);
+        assert_eq!(rgba(image.pixels[1]), [0., 0., 1., 1.]);
+    }
+
+    #[test]
+    fn reads_rle_grey_and_colour_mapped() {
+        let mut data = header(11, None, 3, 1, 8);
+        data.extend([0x81, 255, 0x00, 0]); // a run of two, one literal
+        let image = read_tga(&data).unwrap();
+        let grey: Vec<f64> = image.pixels.iter().map(|p| p.x).collect();
+        assert_eq!(grey, vec![1., 1., 0.]);
+
+        let mut data = header(1, Some((2, 32)), 2, 1, 8);
+        data.extend([0, 0, 0, 0, 255, 255, 255, 255]);
+        data.extend([1, 0]);
+        let image = read_tga(&data).unwrap();
+        assert!(image.alpha);
+        assert_eq!(rgba(image.pixels[0]), [1., 1., 1., 1.]);
+        assert_eq!(rgba(image.pixels[1]), [0., 0., 0., 0.]);
+    }
+
+    #[test]
+    fn rejects_bad_colour_map_depths() {
+        for bits in [0, 8, 33] {
+            let mut data = header(1, Some((2, bits)), 2, 1, 8);
+            data.extend([0; 16]);
+            assert!(read_tga(&data).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_sizes_the_data_cannot_hold() {
+        let mut data = header(10, None, 4096, 4096, 32);
+        data.extend([0xff, 1, 2, 3, 4]);
+        assert!(read_tga(&data).is_err());
+
+        let mut data = header(2, None, 64, 64, 24);
+        data.extend([0; 100]);
+        assert!(read_tga(&data).is_err());
+        assert!(read_tga(&data[..10]).is_err());
+    }
+}
@@ -0,0 +1,154 @@
+use crate::vec4::Vec4;
+
+use std::str::FromStr;
+
+// turns the linear render into display values in [0, 1] for the 8 and 16
+// bit outputs, the float formats are written untouched
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ToneMapOp {
+    Clamp,
+    Reinhard,
+    ReinhardExtended,
+    Hable,
+    Aces,
+}
+
+impl FromStr for ToneMapOp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ToneMapOp, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "clamp" | "none" => Ok(ToneMapOp::Clamp),
+            "reinhard" => Ok(ToneMapOp::Reinhard),
+            "reinhard-extended" => Ok(ToneMapOp::ReinhardExtended),
+            "hable" | "filmic" | "uncharted" => Ok(ToneMapOp::Hable),
+            "aces" => Ok(ToneMapOp::Aces),
+            _ => Err(format!("unknown tone mapping operator: {}", s)),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Transfer {
+    Srgb,
+    Gamma, // plain 2.2 power, what the renderer used to write
+}
+
+impl FromStr for Transfer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Transfer, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "srgb" => Ok(Transfer::Srgb),
+            "gamma" | "2.2" => Ok(Transfer::Gamma),
+            _ => Err(format!("unknown transfer function: {}", s)),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ToneMapper {
+    pub op: ToneMapOp,
+    pub exposure: f64,      // in stops, every +1 doubles the brightness
+    pub white: Option<f64>, // luminance mapped to 1 by extended reinhard
+    pub transfer: Transfer,
+}
+
+impl ToneMapper {
+    pub fn apply(&self, screen: &[Vec4]) -> Vec<Vec4> {
+        let scale = 2f64.powf(self.exposure);
+        // extended reinhard defaults to the brightest pixel as white
+        let white = self.white.unwrap_or_else(|| {
+            screen
+                .iter()
+                .map(|c| c.luminance() * scale)
+                .filter(|l| l.is_finite())
+                .fold(1e-6, f64::max)
+        });
+
+        return screen
+            .iter()
+            .map(|c| {
+                let linear = Vec4::new(c.x * scale, c.y * scale, c.z * scale, 0.);
+                let mapped = self.map(linear, white);
+                let encode = |v: f64| {
+                    let v = v.clamp(0., 1.);
+                    match self.transfer {
+                        Transfer::Srgb => srgb(v),
+                        Transfer::Gamma => v.powf(1. / 2.2),
+                    }
+                };
+                // alpha is coverage, not light
+                Vec4::new(
+                    encode(mapped.x),
+                    encode(mapped.y),
+                    encode(mapped.z),
+                    c.w.clamp(0., 1.),
+                )
+            })
+            .collect();
+    }
+
+    fn map(&self, c: Vec4, white: f64) -> Vec4 {
+        match self.op {
+            ToneMapOp::Clamp => c,
+            // reinhard et al. 2002 on luminance so hues don't shift
+            // https://www.cs.utah.edu/docs/techreports/2002/pdf/UUCS-02-001.pdf
+            ToneMapOp::Reinhard => scale_luminance(c, |l| l / (1. + l)),
+            ToneMapOp::ReinhardExtended => {
+                scale_luminance(c, |l| l * (1. + l / (white * white)) / (1. + l))
+            }
+            ToneMapOp::Hable => {
+                // filmic curve from uncharted 2, with its usual exposure bias
+                // http://filmicworlds.com/blog/filmic-tonemapping-operators/
+                let w = hable(11.2);
+                let f = |v: f64| hable(v * 2.) / w;
+                Vec4::new(f(c.x), f(c.y), f(c.z), 0.)
+            }
+            ToneMapOp::Aces => aces_fitted(c),
+        }
+    }
+}
+
+fn scale_luminance(c: Vec4, f: impl Fn(f64) -> f64) -> Vec4 {
+    let l = c.luminance();
+    if l <= 0. {
+        return Vec4::new(0., 0., 0., 0.);
+    }
+    return c * (f(l) / l);
+}
+
+fn hable(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    return (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f;
+}
+
+// stephen hill's fit of the aces reference rendering and srgb output
+// transforms, in and out of the aces working space around the curve
+// https://github.com/TheRealMJP/BakingLab/blob/master/BakingLab/ACES.hlsl
+fn aces_fitted(c: Vec4) -> Vec4 {
+    let v = Vec4::new(
+        0.59719 * c.x + 0.35458 * c.y + 0.04823 * c.z,
+        0.07600 * c.x + 0.90834 * c.y + 0.01566 * c.z,
+        0.02840 * c.x + 0.13383 * c.y + 0.83777 * c.z,
+        0.,
+    );
+    let f =
+        |v: f64| (v * (v + 0.0245786) - 0.000090537) / (v * (0.983729 * v + 0.4329510) + 0.238081);
+    let v = Vec4::new(f(v.x), f(v.y), f(v.z), 0.);
+    return Vec4::new(
+        1.60475 * v.x - 0.53108 * v.y - 0.07367 * v.z,
+        -0.10208 * v.x + 1.10813 * v.y - 0.00605 * v.z,
+        -0.00327 * v.x - 0.07276 * v.y + 1.07602 * v.z,
+        0.,
+    );
+}
+
+// iec 61966-2-1 encoding, linear near black and a 2.4 power above
+fn srgb(v: f64) -> f64 {
+    if v <= 0.0031308 {
+        return 12.92 * v;
+    }
+    return 1.055 * v.powf(1. / 2.4) - 0.055;
+}
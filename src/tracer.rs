@@ -1,17 +1,25 @@
-use crate::mat4::Mat4;
+use crate::bsdf::{Bsdf, BsdfSample};
+use crate::camera::Camera;
+use crate::film::Film;
+use crate::filter::Filter;
+use crate::material::Material;
+use crate::microfacet::{reflect, refract};
 use crate::obj::Obj;
+use crate::sampler::{new_sampler, Sampler, SamplerKind};
+use crate::sampling::{power_heuristic, Frame};
 use crate::triangle::Triangle;
 use crate::vec4::Vec4;
 
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
 use std::thread;
-use std::thread::{JoinHandle, Thread};
 
 use std::cmp;
+use std::f64::consts::PI;
+use std::str::FromStr;
 
-const EPSILON: f64 = 0.0000001;
-const NUM_THREADS: usize = 16;
-const MAX_DEPTH: usize = 4;
+pub const MAX_DEPTH: usize = 4;
+const RR_DEPTH: usize = 3; // start russian roulette after this many bounces
 
 const AMBIENT_COLOR: Vec4 = Vec4 {
     x: 1.,
@@ -20,122 +28,219 @@ const AMBIENT_COLOR: Vec4 = Vec4 {
     w: 1.,
 };
 
-pub struct Ray {
-    pub origin: Vec4,
-    pub dir: Vec4,
+const SKY_COLOR: Vec4 = Vec4 {
+    x: 0.52,
+    y: 0.80,
+    z: 0.92,
+    w: 1.,
+};
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Integrator {
+    BlinnPhong, // single hit, whitted-style shading with an ambient term
+    PathTracer, // unidirectional monte carlo path tracing
 }
 
-struct Intersection {
-    p: Vec4,
-    t: f64,
-    triangle: Triangle,
+impl FromStr for Integrator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Integrator, String> {
+        match s {
+            "blinn-phong" | "phong" => Ok(Integrator::BlinnPhong),
+            "path" | "pathtracer" => Ok(Integrator::PathTracer),
+            _ => Err(format!("unknown integrator: {}", s)),
+        }
+    }
 }
 
-pub struct Light {
-    pub pos: Vec4,
-    pub col: Vec4,
+#[derive(Copy, Clone)]
+pub struct RenderSettings {
+    pub integrator: Integrator,
+    pub spp: usize,       // samples per pixel
+    pub max_depth: usize, // max number of bounces for the path tracer
+    pub threads: usize,
+    pub filter: Filter, // reconstruction filter samples are splatted with
+    pub sampler: SamplerKind,
+    pub seed: u64, // same seed, same image
 }
 
-fn intersects(r: &Ray, t: &Triangle) -> Option<Intersection> {
-    // moller-trumbore intersection test
-    // adapted from https://www.scratchapixel.com/lessons/3d-basic-rendering/ray-tracing-rendering-a-triangle/moller-trumbore-ray-triangle-intersection.html
+#[derive(Copy, Clone)]
+pub struct Ray {
+    pub origin: Vec4,
+    pub dir: Vec4,
+}
 
-    let p0p1: Vec4 = t.p1.v - t.p0.v;
-    let p0p2: Vec4 = t.p2.v - t.p0.v;
-    let pvec: Vec4 = (r.dir).cross(p0p2);
-    let det: f64 = p0p1.dot(pvec);
+// rays through the neighbouring pixels, one over in x and one down in y,
+// followed along with a camera ray so the footprint of the pixel is known
+// where it lands and textures can be filtered over it
+// https://pbr-book.org/3ed-2018/Texture/Sampling_and_Antialiasing#FindingtheTextureSamplingRate
+#[derive(Copy, Clone)]
+struct Differentials {
+    rx: Ray,
+    ry: Ray,
+}
 
-    if det.abs() < EPSILON {
-        return None;
+impl Differentials {
+    // pulled towards r by scale, with more samples per pixel each one only
+    // has to cover its share of the pixel
+    fn new(r: &Ray, rx: Ray, ry: Ray, scale: f64) -> Differentials {
+        let pull = |d: Ray| -> Ray {
+            return Ray {
+                origin: r.origin + (d.origin - r.origin) * scale,
+                dir: (r.dir + (d.dir - r.dir) * scale).normalize(),
+            };
+        };
+        return Differentials {
+            rx: pull(rx),
+            ry: pull(ry),
+        };
     }
 
-    let inv_det: f64 = 1. / det;
-    let tvec: Vec4 = r.origin - t.p0.v;
-    let u: f64 = tvec.dot(pvec) * inv_det;
-
-    if u < 0.0 || u > 1.0 {
-        return None;
+    // offsets from p to where the neighbouring rays cross the plane through
+    // p with normal n
+    fn footprint(&self, p: &Vec4, n: &Vec4) -> Option<(Vec4, Vec4)> {
+        let offset = |r: &Ray| -> Option<Vec4> {
+            let d = n.dot(r.dir);
+            if d.abs() < 1e-12 {
+                return None;
+            }
+            let t = n.dot(*p - r.origin) / d;
+            return Some(r.origin + r.dir * t - *p);
+        };
+        return Some((offset(&self.rx)?, offset(&self.ry)?));
     }
 
-    let qvec: Vec4 = tvec.cross(p0p1);
-    let v: f64 = (r.dir).dot(qvec) * inv_det;
-
-    if v < 0.0 || u + v > 1.0 {
-        return None;
+    // carrying on through p unchanged, for dissolved surfaces
+    fn pass(&self, p: &Vec4, dp: (Vec4, Vec4)) -> Differentials {
+        return Differentials {
+            rx: Ray {
+                origin: *p + dp.0,
+                dir: self.rx.dir,
+            },
+            ry: Ray {
+                origin: *p + dp.1,
+                dir: self.ry.dir,
+            },
+        };
     }
 
-    let it: f64 = p0p2.dot(qvec) * inv_det;
-    if it <= 0. {
-        return None;
+    // after a specular bounce at p on t, each neighbouring ray leaves from
+    // its own point on the surface, mirrored or refracted about the shading
+    // normal there. side flips the normals the way the main ray's was
+    fn bounce(
+        &self,
+        p: &Vec4,
+        t: &Triangle,
+        dp: (Vec4, Vec4),
+        side: f64,
+        transmit: Option<f64>,
+    ) -> Option<Differentials> {
+        let turn = |r: &Ray, d: Vec4| -> Option<Ray> {
+            let q: Vec4 = *p + d;
+            let n: Vec4 = shading_normal(&q, t) * side;
+            let wo: Vec4 = r.dir * -1.;
+            let dir = match transmit {
+                Some(eta) => refract(&wo, &n, eta)?,
+                None => reflect(&wo, &n),
+            };
+            return Some(Ray {
+                origin: q,
+                dir: dir,
+            });
+        };
+        return Some(Differentials {
+            rx: turn(&self.rx, dp.0)?,
+            ry: turn(&self.ry, dp.1)?,
+        });
     }
+}
+
+struct Intersection<'a> {
+    p: Vec4,
+    t: f64,
+    triangle: &'a Triangle,
+}
 
+pub struct Light {
+    pub pos: Vec4,
+    pub col: Vec4,
+}
+
+fn closest_hit<'a>(r: &Ray, object: &'a Obj) -> Option<Intersection<'a>> {
+    let (i, t) = object
+        .accel
+        .closest_hit(&object.triangles, r, f64::INFINITY)?;
     return Some(Intersection {
-        p: r.origin + r.dir * it,
-        t: it,
-        triangle: *t,
+        p: r.origin + r.dir * t,
+        t: t,
+        triangle: &object.triangles[i],
     });
 }
 
-fn brdf(p: &Vec4, t: &Triangle, cam: &Vec4, lights: &Vec<Light>, object: &Obj) -> Vec4 {
-    // blinn-phong brdf
+fn shading_normal(p: &Vec4, t: &Triangle) -> Vec4 {
+    let n: Vec4 = t.normal_interp(p);
+    if n.x.is_nan() {
+        return t.normal(); // fallback to surface normal if interpolation fails
+    }
+    return n;
+}
 
-    let ns = t.mat.unwrap().ns;
-    let ka = t.mat.unwrap().ka;
-    let kd = t.mat.unwrap().kd;
-    let ks = t.mat.unwrap().ks;
-    let ambient: Vec4 = AMBIENT_COLOR * ka;
+fn occluded(p: &Vec4, n: &Vec4, target: &Vec4, object: &Obj) -> bool {
+    // shadow ray from p towards target, anything in between blocks it
+    let origin: Vec4 = *p + *n * 0.0001;
+    let d: Vec4 = *target - origin;
+    let dist = d.length();
+    let r: Ray = Ray {
+        origin: origin,
+        dir: d / dist,
+    };
+
+    return object.accel.any_hit(&object.triangles, &r, dist - 0.0001);
+}
 
-    //let N: Vec4 = t.normal3p();
-    //let mut N: Vec4 = t.normal();
+fn brdf(
+    p: &Vec4,
+    t: &Triangle,
+    dp: Option<(Vec4, Vec4)>,
+    cam: &Vec4,
+    lights: &Vec<Light>,
+    object: &Obj,
+    sampler: &mut dyn Sampler,
+) -> Vec4 {
+    // blinn-phong brdf
 
-    let mut N: Vec4 = t.normal_interp(&p);
-    if N.x.is_nan() {
-        N = t.normal(); // fallback to surface normal if interpolation fails
-    }
+    let mat: Material = object.material_at(t, p, dp);
+    let ns = mat.ns;
+    let ka = mat.ka;
+    let kd = mat.kd;
+    let ks = mat.ks;
+    let ambient: Vec4 = AMBIENT_COLOR * ka;
 
-    let V: Vec4 = (*cam - *p).normalize();
+    let n: Vec4 = shading_normal(p, t);
+    let v: Vec4 = (*cam - *p).normalize();
 
-    let mut col: Vec4 = ambient;
+    let mut col: Vec4 = ambient + mat.ke;
 
     for light in lights {
-        let mut lN: Vec4 = N;
-        let L: Vec4 = (light.pos - *p).normalize();
+        let mut ln: Vec4 = n;
+        let l: Vec4 = (light.pos - *p).normalize();
 
-        let mut lambertian = N.dot(L);
+        let mut lambertian = n.dot(l);
 
         if lambertian <= 0. {
-            lN = N * -1.;
-            lambertian = lN.dot(L);
+            ln = n * -1.;
+            lambertian = ln.dot(l);
         }
 
-        let r: Ray = Ray {
-            origin: ((*p) + lN * 0.0001),
-            dir: L,
-        };
-
-        let mut fail = false;
-
-        for t in object.head.ray_leaf(&r) {
-            // shadow rays
-            let result: Option<Intersection> = intersects(&r, &t);
-
-            if result.is_some() {
-                let res: Intersection = result.unwrap();
-                if (res.p - *p).length() < (light.pos - *p).length() {
-                    fail = true;
-                    break;
-                }
-            }
-        }
-        if fail == true {
+        if occluded(p, &ln, &light.pos, object) {
             continue;
         }
 
         let diffuse = light.col * kd * lambertian;
 
-        let H: Vec4 = (L + V).normalize();
+        let h: Vec4 = (l + v).normalize();
 
-        let spec: f64 = lN.dot(H);
+        let spec: f64 = ln.dot(h);
 
         if spec <= 0. {
             col += diffuse;
@@ -146,87 +251,359 @@ fn brdf(p: &Vec4, t: &Triangle, cam: &Vec4, lights: &Vec<Light>, object: &Obj) -
 
         col += diffuse + specular;
     }
-    if (col.x.is_nan() || col.y.is_nan() || col.z.is_nan()) {
+
+    // one sample on the emissive triangles per call, soft shadows converge
+    // as samples per pixel go up
+    let u0 = sampler.next_1d();
+    let (u1, u2) = sampler.next_2d();
+    if let Some(ls) = object.emitters.sample(p, u0, u1, u2) {
+        let mut ln: Vec4 = n;
+        let mut lambertian = n.dot(ls.wi);
+
+        if lambertian <= 0. {
+            ln = n * -1.;
+            lambertian = ln.dot(ls.wi);
+        }
+
+        if !occluded(p, &ln, &ls.p, object) {
+            // normalized blinn-phong so area lights keep their radiometry
+            let h: Vec4 = (ls.wi + v).normalize();
+            let spec: f64 = f64::max(ln.dot(h), 0.);
+            let f: Vec4 = kd / PI + ks * ((ns + 8.) / (8. * PI) * spec.powf(ns));
+
+            col += ls.le * f * (lambertian / ls.pdf);
+        }
+    }
+
+    if col.x.is_nan() || col.y.is_nan() || col.z.is_nan() {
         println!("NaN value fixme");
         return Vec4::new(0., 0., 0., 0.); // hack to cover weird case idk why this happens
     }
     return col;
 }
 
-pub fn raytrace(
-    screen: &Arc<Mutex<Vec<Vec4>>>,
-    object: &'static Obj,
-    cam: &'static Vec4,
-    lights: &'static Vec<Light>,
-    res_x: usize,
-    res_y: usize,
-    m: &'static Mat4,
-) {
-    let mut threads: Vec<JoinHandle<_>> = Vec::new();
-
-    let lines_per_thread = res_y / NUM_THREADS;
-
-    for c in 0..(NUM_THREADS) {
-        let screen_mutex_clone = Arc::clone(screen);
-        println!("starting thread {c}");
-
-        threads.push(thread::spawn(move || {
-            for i in (c * lines_per_thread)..(cmp::min((c + 1) * lines_per_thread, res_y)) {
-                if i % 15 == 0 {
-                    println!(
-                        "thread {}: working on {}/{}",
-                        c,
-                        i - (c * lines_per_thread),
-                        cmp::min((c + 1) * lines_per_thread, res_y) - c * lines_per_thread
-                    );
-                }
-                for j in 0..res_x {
-                    let mut res: Vec4 = Vec4::new(0., 0., 0., 0.);
-                    let ux = ((j as f64 / res_x as f64) * 2. - 1.) * (res_x as f64 / res_y as f64);
-                    let uy = -((i as f64 / res_y as f64) * 2. - 1.);
-
-                    let d = Vec4::new(ux, uy, -2., 0.).normalize();
-
-                    let r: Ray = Ray {
-                        origin: (*m) * (*cam),
-                        dir: (*m) * d,
-                    };
-
-                    let mut intersections: Vec<Intersection> = Vec::new();
-                    for t in object.head.ray_leaf(&r) {
-                        let result: Option<Intersection> = intersects(&r, &t);
-                        if result.is_none() {
-                            continue;
-                        }
+fn direct_light(
+    p: &Vec4,
+    frame: &Frame,
+    wo: &Vec4,
+    bsdf: &Bsdf,
+    lights: &[Light],
+    object: &Obj,
+    sampler: &mut dyn Sampler,
+) -> Vec4 {
+    // next event estimation, same shadow rays as the blinn-phong brdf but
+    // weighted by the actual bsdf and the pdf of the light sample
+    let mut col: Vec4 = Vec4::new(0., 0., 0., 0.);
+
+    for light in lights {
+        // point lights are a delta distribution, bsdf sampling can never
+        // hit them so there is nothing to weight against
+        let d: Vec4 = light.pos - *p;
+        let dist2 = d.dot(d);
+        let wi: Vec4 = frame.to_local(d.normalize());
+        let f: Vec4 = bsdf.eval(wo, &wi);
+        if f.max3() <= 0. {
+            continue;
+        }
+        let off: Vec4 = frame.n * wi.z.signum();
+        if occluded(p, &off, &light.pos, object) {
+            continue;
+        }
+        col += f * light.col * (wi.z.abs() / dist2);
+    }
 
-                        intersections.push(result.unwrap());
-                    }
+    let u0 = sampler.next_1d();
+    let (u1, u2) = sampler.next_2d();
+    if let Some(ls) = object.emitters.sample(p, u0, u1, u2) {
+        let wi: Vec4 = frame.to_local(ls.wi);
+        let f: Vec4 = bsdf.eval(wo, &wi);
+        if f.max3() > 0. {
+            let off: Vec4 = frame.n * wi.z.signum();
+            if !occluded(p, &off, &ls.p, object) {
+                let weight = power_heuristic(ls.pdf, bsdf.pdf(wo, &wi));
+                col += f * ls.le * (wi.z.abs() * weight / ls.pdf);
+            }
+        }
+    }
 
-                    if intersections.len() == 0 {
-                        res = Vec4::new(0.52, 0.80, 0.92, 1.0);
-                    } else {
-                        let mut min_inter: &Intersection = &intersections[0];
+    return col;
+}
 
-                        for inter in &intersections {
-                            if inter.t < min_inter.t {
-                                min_inter = &inter;
-                            }
-                        }
-                        res = brdf(&min_inter.p, &min_inter.triangle, &cam, &lights, &object);
-                    }
+fn pathtrace(
+    r: Ray,
+    diff: Option<Differentials>,
+    object: &Obj,
+    lights: &[Light],
+    max_depth: usize,
+    sampler: &mut dyn Sampler,
+) -> Vec4 {
+    // iterative form of the rendering equation estimator. light comes from
+    // the sky, point lights and emissive triangles, the latter two being
+    // sampled explicitly at every vertex
+    let mut col: Vec4 = Vec4::new(0., 0., 0., 0.);
+    let mut throughput: Vec4 = Vec4::new(1., 1., 1., 1.);
+    let mut r: Ray = r;
+    let mut diff: Option<Differentials> = diff;
+    let mut depth = 0;
+
+    // previous vertex, needed to mis weight emitters found by bsdf sampling
+    let mut prev_p: Vec4 = r.origin;
+    let mut prev_pdf: f64 = 0.;
+    let mut prev_specular = true;
+
+    // whether the camera ray hit anything, returned in w
+    let mut alpha: f64 = 0.;
+
+    loop {
+        let hit: Intersection = match closest_hit(&r, object) {
+            Some(x) => x,
+            None => {
+                col += throughput * SKY_COLOR;
+                break;
+            }
+        };
 
-                    {
-                        let mut data = screen_mutex_clone.lock().unwrap();
-                        data[i * res_x + j] = res;
-                    }
-                }
+        let dp = diff.and_then(|d| d.footprint(&hit.p, &hit.triangle.normal()));
+        let mat: Material = object.material_at(hit.triangle, &hit.p, dp);
+
+        let le: Vec4 = mat.ke;
+        if le.max3() > 0. {
+            if prev_specular {
+                // camera rays and specular bounces never sample lights
+                col += throughput * le;
+            } else {
+                let light_pdf = object.emitters.pdf(hit.triangle, &le, &prev_p, &hit.p);
+                col += throughput * le * power_heuristic(prev_pdf, light_pdf);
             }
-            println!("{c} finished");
-        }));
+        }
+
+        // dissolve is treated as coverage, the rest of the time the ray
+        // carries on as if the surface wasn't there
+        if mat.d < 1. && sampler.next_1d() >= mat.d {
+            r = Ray {
+                origin: hit.p + r.dir * 0.0001,
+                dir: r.dir,
+            };
+            diff = diff.zip(dp).map(|(d, dp)| d.pass(&hit.p, dp));
+            continue;
+        }
+
+        if depth == 0 {
+            alpha = 1.;
+        }
+        if depth == max_depth {
+            break;
+        }
+
+        let entering = r.dir.dot(hit.triangle.normal()) < 0.;
+        let bsdf: Bsdf = Bsdf::from_material(&mat, entering);
+
+        // leaving a refractive object, attenuate by the distance travelled
+        // inside it (beer-lambert)
+        if !entering && bsdf.is_transmissive() {
+            let sigma: Vec4 = mat.absorption() * -hit.t;
+            throughput *= Vec4::new(sigma.x.exp(), sigma.y.exp(), sigma.z.exp(), 1.);
+        }
+
+        let mut n: Vec4 = shading_normal(&hit.p, hit.triangle);
+        let side = if n.dot(r.dir) > 0. { -1. } else { 1. };
+        n *= side;
+        let frame: Frame = Frame::new(n);
+        let wo: Vec4 = frame.to_local(r.dir * -1.);
+
+        col += throughput * direct_light(&hit.p, &frame, &wo, &bsdf, lights, object, sampler);
+
+        let (u1, u2) = sampler.next_2d();
+        let uc = sampler.next_1d();
+        let bs: BsdfSample = match bsdf.sample(&wo, u1, u2, uc) {
+            Some(x) => x,
+            None => break,
+        };
+        throughput *= bs.weight;
+
+        if depth >= RR_DEPTH {
+            let q = throughput.max3().clamp(0.05, 1.);
+            if sampler.next_1d() >= q {
+                break;
+            }
+            throughput /= q;
+        }
+
+        // only specular bounces keep the footprint meaningful, past a
+        // glossy or diffuse one textures are looked up unfiltered
+        diff = match diff.zip(dp) {
+            Some((d, dp)) if bs.specular => {
+                let transmit = if bs.wi.z < 0. { Some(bsdf.eta()) } else { None };
+                d.bounce(&hit.p, hit.triangle, dp, side, transmit)
+            }
+            _ => None,
+        };
+
+        prev_p = hit.p;
+        prev_pdf = bs.pdf;
+        prev_specular = bs.specular;
+
+        r = Ray {
+            origin: hit.p + n * (0.0001 * bs.wi.z.signum()),
+            dir: frame.to_world(bs.wi),
+        };
+        depth += 1;
     }
-    let mut i = 0;
-    for t in threads {
-        let t_ = t.join();
-        i += 1;
+
+    if col.x.is_nan() || col.y.is_nan() || col.z.is_nan() {
+        return Vec4::new(0., 0., 0., alpha);
+    }
+    return Vec4::new(col.x, col.y, col.z, alpha);
+}
+
+// square blocks of pixels handed out to the render threads one at a time,
+// small enough that the threads all finish at about the same time
+const TILE_SIZE: usize = 16;
+
+struct Tile {
+    x0: usize,
+    y0: usize,
+    x1: usize,
+    y1: usize,
+}
+
+fn tiles(res_x: usize, res_y: usize) -> Vec<Tile> {
+    let mut tiles: Vec<Tile> = Vec::new();
+    for y0 in (0..res_y).step_by(TILE_SIZE) {
+        for x0 in (0..res_x).step_by(TILE_SIZE) {
+            tiles.push(Tile {
+                x0: x0,
+                y0: y0,
+                x1: cmp::min(x0 + TILE_SIZE, res_x),
+                y1: cmp::min(y0 + TILE_SIZE, res_y),
+            });
+        }
+    }
+    return tiles;
+}
+
+// renders the samples of every pixel in tile into a film grown by the filter
+// radius, clipped to the image
+fn render_tile(
+    tile: &Tile,
+    object: &Obj,
+    camera: &Camera,
+    lights: &Vec<Light>,
+    res_x: usize,
+    res_y: usize,
+    settings: &RenderSettings,
+) -> Film {
+    let margin = settings.filter.radius.ceil() as usize;
+    let fx0 = tile.x0.saturating_sub(margin);
+    let fy0 = tile.y0.saturating_sub(margin);
+    let fx1 = cmp::min(tile.x1.saturating_add(margin), res_x);
+    let fy1 = cmp::min(tile.y1.saturating_add(margin), res_y);
+    let mut film = Film::new(fx0, fy0, fx1 - fx0, fy1 - fy0);
+
+    let mut sampler = new_sampler(settings.sampler, settings.spp, settings.seed);
+    let diff_scale = f64::max(0.125, 1. / (settings.spp as f64).sqrt());
+
+    for i in tile.y0..tile.y1 {
+        for j in tile.x0..tile.x1 {
+            for k in 0..settings.spp {
+                sampler.start_pixel_sample(j, i, k);
+
+                // a single sample goes through the pixel center
+                let (ox, oy) = if settings.spp == 1 {
+                    (0.5, 0.5)
+                } else {
+                    sampler.next_2d()
+                };
+                let x = j as f64 + ox;
+                let y = i as f64 + oy;
+                let (lens_u, lens_v) = sampler.next_2d();
+                let r: Ray = camera.ray(x / res_x as f64, y / res_y as f64, lens_u, lens_v);
+                let rx: Ray = camera.ray((x + 1.) / res_x as f64, y / res_y as f64, lens_u, lens_v);
+                let ry: Ray = camera.ray(x / res_x as f64, (y + 1.) / res_y as f64, lens_u, lens_v);
+                let diff = Differentials::new(&r, rx, ry, diff_scale);
+
+                let l: Vec4 = match settings.integrator {
+                    Integrator::BlinnPhong => match closest_hit(&r, object) {
+                        Some(hit) => {
+                            let dp = diff.footprint(&hit.p, &hit.triangle.normal());
+                            let c = brdf(
+                                &hit.p,
+                                hit.triangle,
+                                dp,
+                                &camera.eye,
+                                lights,
+                                object,
+                                &mut *sampler,
+                            );
+                            Vec4::new(c.x, c.y, c.z, 1.)
+                        }
+                        None => Vec4::new(SKY_COLOR.x, SKY_COLOR.y, SKY_COLOR.z, 0.),
+                    },
+                    Integrator::PathTracer => pathtrace(
+                        r,
+                        Some(diff),
+                        object,
+                        lights,
+                        settings.max_depth,
+                        &mut *sampler,
+                    ),
+                };
+                film.add_sample(x, y, l, &settings.filter);
+            }
+        }
     }
+    return film;
+}
+
+// renders the image with settings.threads threads pulling tiles off a shared
+// counter, each tile goes into its own film and is merged into the image
+// once finished so the threads never wait on each other per pixel
+pub fn raytrace(
+    object: &Obj,
+    camera: &Camera,
+    lights: &Vec<Light>,
+    res_x: usize,
+    res_y: usize,
+    settings: RenderSettings,
+) -> Vec<Vec4> {
+    let mut film = Film::new(0, 0, res_x, res_y);
+    let tiles: Vec<Tile> = tiles(res_x, res_y);
+    let next = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel::<Film>();
+
+    let num_threads = cmp::min(settings.threads, tiles.len());
+    println!("rendering {} tiles on {} threads", tiles.len(), num_threads);
+
+    thread::scope(|s| {
+        for _ in 0..num_threads {
+            let tx = tx.clone();
+            let tiles = &tiles;
+            let next = &next;
+            let settings = &settings;
+            s.spawn(move || loop {
+                let k = next.fetch_add(1, Ordering::Relaxed);
+                if k >= tiles.len() {
+                    break;
+                }
+                let tile = &tiles[k];
+                let tile_film = render_tile(tile, object, camera, lights, res_x, res_y, settings);
+                if tx.send(tile_film).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(tx);
+
+        let mut done = 0;
+        for tile_film in rx {
+            film.merge(&tile_film);
+
+            done += 1;
+            if done * 10 / tiles.len() != (done - 1) * 10 / tiles.len() {
+                println!("{}%", done * 100 / tiles.len());
+            }
+        }
+    });
+
+    return film.resolve();
 }
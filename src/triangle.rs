@@ -1,16 +1,60 @@
 use crate::aabb::AABB;
-use crate::material::Material;
+use crate::texture::TexCoord;
+use crate::tracer::Ray;
 use crate::vec4::{NVec4, Vec4};
 
+const EPSILON: f64 = 0.0000001;
+
 #[derive(Copy, Clone)]
 pub struct Triangle {
     pub p0: NVec4,
     pub p1: NVec4,
     pub p2: NVec4,
-    pub mat: Option<Material>,
+    // texture coordinates of each corner in x and y, zero if the obj has none
+    pub t0: Vec4,
+    pub t1: Vec4,
+    pub t2: Vec4,
+    pub mat: usize, // index into Obj::materials
 }
 
 impl Triangle {
+    // distance along r to the triangle, if it's hit
+    pub fn intersect(&self, r: &Ray) -> Option<f64> {
+        // moller-trumbore intersection test
+        // adapted from https://www.scratchapixel.com/lessons/3d-basic-rendering/ray-tracing-rendering-a-triangle/moller-trumbore-ray-triangle-intersection.html
+
+        let p0p1: Vec4 = self.p1.v - self.p0.v;
+        let p0p2: Vec4 = self.p2.v - self.p0.v;
+        let pvec: Vec4 = (r.dir).cross(p0p2);
+        let det: f64 = p0p1.dot(pvec);
+
+        if det.abs() < EPSILON {
+            return None;
+        }
+
+        let inv_det: f64 = 1. / det;
+        let tvec: Vec4 = r.origin - self.p0.v;
+        let u: f64 = tvec.dot(pvec) * inv_det;
+
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let qvec: Vec4 = tvec.cross(p0p1);
+        let v: f64 = (r.dir).dot(qvec) * inv_det;
+
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let it: f64 = p0p2.dot(qvec) * inv_det;
+        if it <= 0. {
+            return None;
+        }
+
+        return Some(it);
+    }
+
     pub fn normal(&self) -> Vec4 {
         let a: Vec4 = self.p1.v - self.p0.v;
         let b: Vec4 = self.p2.v - self.p0.v;
@@ -19,6 +63,41 @@ impl Triangle {
     }
 
     pub fn normal_interp(&self, p: &Vec4) -> Vec4 {
+        let (u, v, w) = self.barycentric(p);
+        return (self.p0.n * u + self.p1.n * v + self.p2.n * w).normalize();
+    }
+
+    // texture coordinates at p
+    pub fn uv_interp(&self, p: &Vec4) -> Vec4 {
+        let (u, v, w) = self.barycentric(p);
+        return self.t0 * u + self.t1 * v + self.t2 * w;
+    }
+
+    // texture coordinates at p, with derivatives when the offsets dpdx and
+    // dpdy to where the neighbouring pixels hit the surface are known
+    pub fn tex_coord(&self, p: &Vec4, dp: Option<(Vec4, Vec4)>) -> TexCoord {
+        let uv = self.uv_interp(p);
+        let (duvdx, duvdy) = match dp {
+            Some((dpdx, dpdy)) => {
+                // barycentrics are affine in p, so their change along an
+                // offset is that of p0 moved by it
+                let duv = |d: Vec4| -> Vec4 {
+                    let (_, b1, b2) = self.barycentric(&(self.p0.v + d));
+                    return (self.t1 - self.t0) * b1 + (self.t2 - self.t0) * b2;
+                };
+                (duv(dpdx), duv(dpdy))
+            }
+            None => (Vec4::new(0., 0., 0., 0.), Vec4::new(0., 0., 0., 0.)),
+        };
+        return TexCoord {
+            uv: uv,
+            duvdx: duvdx,
+            duvdy: duvdy,
+        };
+    }
+
+    // weights of p0, p1 and p2 for a point p on the triangle
+    pub fn barycentric(&self, p: &Vec4) -> (f64, f64, f64) {
         let v0 = self.p1.v - self.p0.v;
         let v1 = self.p2.v - self.p0.v;
         let v2 = *p - self.p0.v;
@@ -35,27 +114,34 @@ impl Triangle {
         let w = (d00 * d21 - d01 * d20) / denom;
         let u = 1. - v - w;
 
-        return (self.p0.n * u + self.p1.n * v + self.p2.n * w).normalize();
+        return (u, v, w);
     }
 
-    pub fn normal3p(&self) -> Vec4 {
-        return (self.p0.n + self.p1.n + self.p2.n).normalize();
+    pub fn area(&self) -> f64 {
+        let a: Vec4 = self.p1.v - self.p0.v;
+        let b: Vec4 = self.p2.v - self.p0.v;
+
+        return a.cross(b).length() * 0.5;
     }
 
-    pub fn midpoint(&self) -> Vec4 {
-        return (self.p0.v + self.p1.v + self.p2.v) / 3.;
+    pub fn sample_point(&self, u1: f64, u2: f64) -> Vec4 {
+        // uniform point on the triangle from two uniform numbers
+        let su = u1.sqrt();
+        let b0 = 1. - su;
+        let b1 = u2 * su;
+
+        return self.p0.v * b0 + self.p1.v * b1 + self.p2.v * (1. - b0 - b1);
     }
 
     pub fn aabb(&self) -> AABB {
-        let mut min = Vec4::new(0., 0., 0., 0.);
-        let mut max = Vec4::new(0., 0., 0., 0.);
+        // start from a vertex, not the origin, or every box reaches back to it
+        let mut min = self.p0.v;
+        let mut max = self.p0.v;
 
         for i in 0..3 {
-            min.set_elem(i, f64::min(min.elem(i), self.p0.v.elem(i)));
             min.set_elem(i, f64::min(min.elem(i), self.p1.v.elem(i)));
             min.set_elem(i, f64::min(min.elem(i), self.p2.v.elem(i)));
 
-            max.set_elem(i, f64::max(max.elem(i), self.p0.v.elem(i)));
             max.set_elem(i, f64::max(max.elem(i), self.p1.v.elem(i)));
             max.set_elem(i, f64::max(max.elem(i), self.p2.v.elem(i)));
         }
@@ -57,27 +57,14 @@ impl Vec4 {
         };
     }
 
-    pub fn pow(&self, other: f64) -> Vec4 {
-        return Vec4 {
-            x: self.x.powf(other),
-            y: self.y.powf(other),
-            z: self.z.powf(other),
-            w: self.w.powf(other),
-        };
+    pub fn max3(&self) -> f64 {
+        // largest of the colour channels, ignores w
+        f64::max(self.x, f64::max(self.y, self.z))
     }
 
-    pub fn as_rgb(&self) -> Vec4 {
-        // clamp to [0,1], apply gamma correction, multiply by 255, round down
-        let r: f64 = (self.x.clamp(0., 1.).powf(1. / 2.2) * 255.0).floor();
-        let g: f64 = (self.y.clamp(0., 1.).powf(1. / 2.2) * 255.0).floor();
-        let b: f64 = (self.z.clamp(0., 1.).powf(1. / 2.2) * 255.0).floor();
-        let a: f64 = (self.w.clamp(0., 1.).powf(1. / 2.2) * 255.0).floor();
-        return Vec4 {
-            x: r,
-            y: g,
-            z: b,
-            w: a,
-        };
+    pub fn luminance(&self) -> f64 {
+        // rec. 709 weights
+        0.2126 * self.x + 0.7152 * self.y + 0.0722 * self.z
     }
 
     pub fn elem(&self, c: usize) -> f64 {
@@ -180,6 +167,12 @@ impl ops::MulAssign<f64> for Vec4 {
     }
 }
 
+impl ops::MulAssign<Vec4> for Vec4 {
+    fn mul_assign(&mut self, other: Vec4) {
+        *self = (*self) * other;
+    }
+}
+
 impl ops::Div<f64> for Vec4 {
     type Output = Vec4;
 
@@ -0,0 +1,573 @@
+// zlib streams (rfc 1950) around deflate (rfc 1951), for png and exr, and
+// inflate to read png textures back
+// https://www.rfc-editor.org/rfc/rfc1951
+
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+// how many earlier positions with the same hash get compared, more finds
+// longer matches but takes longer
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+// deflate bit streams are filled from the least significant bit up
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, count: u32) {
+        self.bits |= (value as u64) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    // huffman codes are the one thing stored most significant bit first
+    fn write_code(&mut self, code: u32, len: u32) {
+        self.write(code.reverse_bits() >> (32 - len), len);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.bits as u8);
+        }
+        return self.out;
+    }
+}
+
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out: Vec<u8> = vec![0x78, 0x9c]; // 32k window, default compression
+    out.extend(deflate(data));
+    out.extend(adler32(data).to_be_bytes());
+    return out;
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+    // 5552 bytes is the most that can be summed before b can overflow
+    for chunk in data.chunks(5552) {
+        for x in chunk {
+            a += *x as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    return (b << 16) | a;
+}
+
+// lz77 with hash chains, written as a single block with the fixed huffman
+// codes. noisy renders don't compress much either way, dynamic codes
+// aren't worth the extra code here
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut w = BitWriter {
+        out: Vec::with_capacity(data.len() / 2),
+        bits: 0,
+        count: 0,
+    };
+    w.write(1, 1); // last block
+    w.write(1, 2); // fixed huffman codes
+
+    let mut chains = Chains {
+        head: vec![usize::MAX; 1 << HASH_BITS],
+        prev: vec![usize::MAX; WINDOW_SIZE],
+    };
+
+    let mut i = 0;
+    while i < data.len() {
+        let (len, dist) = chains.longest_match(data, i);
+        if len >= MIN_MATCH {
+            write_length(&mut w, len);
+            write_distance(&mut w, dist);
+            for k in i..i + len {
+                chains.insert(data, k);
+            }
+            i += len;
+        } else {
+            write_literal(&mut w, data[i] as u32);
+            chains.insert(data, i);
+            i += 1;
+        }
+    }
+    write_literal(&mut w, 256); // end of block
+    return w.finish();
+}
+
+// earlier positions starting with the same three bytes, newest first.
+// head is indexed by hash, prev by position within the window
+struct Chains {
+    head: Vec<usize>,
+    prev: Vec<usize>,
+}
+
+impl Chains {
+    fn hash(data: &[u8], i: usize) -> usize {
+        let v = (data[i] as u32) | (data[i + 1] as u32) << 8 | (data[i + 2] as u32) << 16;
+        return (v.wrapping_mul(0x9e3779b1) >> (32 - HASH_BITS)) as usize;
+    }
+
+    fn insert(&mut self, data: &[u8], i: usize) {
+        if i + MIN_MATCH <= data.len() {
+            let h = Self::hash(data, i);
+            self.prev[i % WINDOW_SIZE] = self.head[h];
+            self.head[h] = i;
+        }
+    }
+
+    // (length, distance) of the longest earlier match for data[i..]
+    fn longest_match(&self, data: &[u8], i: usize) -> (usize, usize) {
+        if i + MIN_MATCH > data.len() {
+            return (0, 0);
+        }
+        let max_len = usize::min(MAX_MATCH, data.len() - i);
+        let mut best = (0, 0);
+        let mut candidate = self.head[Self::hash(data, i)];
+        let mut chain = 0;
+        while candidate != usize::MAX && i - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+            let mut len = 0;
+            while len < max_len && data[candidate + len] == data[i + len] {
+                len += 1;
+            }
+            if len > best.0 {
+                best = (len, i - candidate);
+                if len == max_len {
+                    break;
+                }
+            }
+            // entries older than the window have been overwritten by newer
+            // positions, those would point forwards
+            let next = self.prev[candidate % WINDOW_SIZE];
+            if next == usize::MAX || next >= candidate {
+                break;
+            }
+            candidate = next;
+            chain += 1;
+        }
+        return best;
+    }
+}
+
+fn write_literal(w: &mut BitWriter, v: u32) {
+    match v {
+        0..=143 => w.write_code(0x30 + v, 8),
+        144..=255 => w.write_code(0x190 + v - 144, 9),
+        256..=279 => w.write_code(v - 256, 7),
+        _ => w.write_code(0xc0 + v - 280, 8),
+    }
+}
+
+fn write_length(w: &mut BitWriter, len: usize) {
+    let code = LENGTH_BASE.partition_point(|b| *b as usize <= len) - 1;
+    write_literal(w, 257 + code as u32);
+    w.write(
+        (len - LENGTH_BASE[code] as usize) as u32,
+        LENGTH_EXTRA[code] as u32,
+    );
+}
+
+fn write_distance(w: &mut BitWriter, dist: usize) {
+    let code = DIST_BASE.partition_point(|b| *b as usize <= dist) - 1;
+    w.write_code(code as u32, 5);
+    w.write(
+        (dist - DIST_BASE[code] as usize) as u32,
+        DIST_EXTRA[code] as u32,
+    );
+}
+
+// the other direction, checks the zlib header and checksum around inflate.
+// output past limit bytes is an error, a few bytes of input can otherwise
+// claim gigabytes
+pub fn decompress(data: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+    if data.len() < 6 {
+        return Err("zlib stream too short".to_string());
+    }
+    let (cmf, flg) = (data[0], data[1]);
+    if cmf & 0x0f != 8 || cmf >> 4 > 7 || !((cmf as u16) << 8 | flg as u16).is_multiple_of(31) {
+        return Err("invalid zlib header".to_string());
+    }
+    if flg & 0x20 != 0 {
+        return Err("zlib preset dictionaries aren't supported".to_string());
+    }
+    let mut r = BitReader {
+        data: &data[2..],
+        pos: 0,
+        bits: 0,
+        count: 0,
+    };
+    let out = inflate(&mut r, limit)?;
+
+    // the checksum starts at the next byte boundary
+    r.align();
+    let mut sum = 0u32;
+    for _ in 0..4 {
+        sum = sum << 8 | r.read(8)?;
+    }
+    if sum != adler32(&out) {
+        return Err("zlib checksum mismatch".to_string());
+    }
+    return Ok(out);
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize, // next byte to load into bits
+    bits: u64,
+    count: u32,
+}
+
+impl BitReader<'_> {
+    fn refill(&mut self) {
+        while self.count <= 56 && self.pos < self.data.len() {
+            self.bits |= (self.data[self.pos] as u64) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+    }
+
+    // up to 32 bits, least significant first
+    fn read(&mut self, n: u32) -> Result<u32, String> {
+        if self.count < n {
+            self.refill();
+            if self.count < n {
+                return Err("unexpected end of compressed data".to_string());
+            }
+        }
+        let v = (self.bits & ((1u64 << n) - 1)) as u32;
+        self.bits >>= n;
+        self.count -= n;
+        return Ok(v);
+    }
+
+    fn align(&mut self) {
+        let skip = self.count % 8;
+        self.bits >>= skip;
+        self.count -= skip;
+    }
+
+    fn decode(&mut self, h: &Huffman) -> Result<u16, String> {
+        if self.count < h.max_len {
+            self.refill();
+        }
+        // past the end the missing bits read as zeros, which is fine as
+        // long as the code found doesn't need them
+        let (symbol, len) = h.table[(self.bits & ((1u64 << h.max_len) - 1)) as usize];
+        if len == 0 {
+            return Err("invalid huffman code".to_string());
+        }
+        if len as u32 > self.count {
+            return Err("unexpected end of compressed data".to_string());
+        }
+        self.bits >>= len;
+        self.count -= len as u32;
+        return Ok(symbol);
+    }
+}
+
+// canonical huffman code as a table indexed by the next max_len bits of
+// the stream, giving the symbol and how many of those bits its code uses
+struct Huffman {
+    table: Vec<(u16, u8)>,
+    max_len: u32,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Huffman, String> {
+        let max_len = lengths.iter().copied().max().unwrap_or(0).max(1) as u32;
+        let mut count = [0u32; 16];
+        for l in lengths {
+            count[*l as usize] += 1;
+        }
+        count[0] = 0;
+
+        // first code of each length, rfc 1951 section 3.2.2
+        let mut next = [0u32; 16];
+        let mut code = 0;
+        let mut left: i64 = 1;
+        for len in 1..16 {
+            code = (code + count[len - 1]) << 1;
+            next[len] = code;
+            left = (left << 1) - count[len] as i64;
+            if left < 0 {
+                return Err("over-subscribed huffman code".to_string());
+            }
+        }
+
+        // incomplete codes are allowed, what they leave out stays invalid
+        let mut table = vec![(0u16, 0u8); 1 << max_len];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len == 0 {
+                continue;
+            }
+            let c = next[len as usize];
+            next[len as usize] += 1;
+            // codes are stored most significant bit first
+            let reversed = c.reverse_bits() >> (32 - len as u32);
+            let mut i = reversed as usize;
+            while i < table.len() {
+                table[i] = (symbol as u16, len);
+                i += 1 << len;
+            }
+        }
+        return Ok(Huffman {
+            table: table,
+            max_len: max_len,
+        });
+    }
+
+    fn fixed() -> (Huffman, Huffman) {
+        let mut lit = [8u8; 288];
+        lit[144..256].fill(9);
+        lit[256..280].fill(7);
+        let dist = [5u8; 30];
+        return (Huffman::new(&lit).unwrap(), Huffman::new(&dist).unwrap());
+    }
+}
+
+// order the code length code lengths are stored in
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+fn inflate(r: &mut BitReader, limit: usize) -> Result<Vec<u8>, String> {
+    let mut out: Vec<u8> = Vec::new();
+    loop {
+        let last = r.read(1)? == 1;
+        match r.read(2)? {
+            0 => {
+                // stored
+                r.align();
+                let len = r.read(16)?;
+                let nlen = r.read(16)?;
+                if len != !nlen & 0xffff {
+                    return Err("corrupt stored block length".to_string());
+                }
+                if out.len() + len as usize > limit {
+                    return Err(too_long(limit));
+                }
+                for _ in 0..len {
+                    out.push(r.read(8)? as u8);
+                }
+            }
+            1 => {
+                let (lit, dist) = Huffman::fixed();
+                inflate_block(r, &lit, &dist, &mut out, limit)?;
+            }
+            2 => {
+                let (lit, dist) = read_dynamic_codes(r)?;
+                inflate_block(r, &lit, &dist, &mut out, limit)?;
+            }
+            _ => return Err("invalid deflate block type".to_string()),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn read_dynamic_codes(r: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    let hlit = r.read(5)? as usize + 257;
+    let hdist = r.read(5)? as usize + 1;
+    let hclen = r.read(4)? as usize + 4;
+
+    let mut cl_lengths = [0u8; 19];
+    for i in CODE_LENGTH_ORDER.iter().take(hclen) {
+        cl_lengths[*i] = r.read(3)? as u8;
+    }
+    let cl = Huffman::new(&cl_lengths)?;
+
+    // literal/length and distance code lengths are one sequence, repeats
+    // may run from one into the other
+    let mut lengths: Vec<u8> = Vec::with_capacity(hlit + hdist);
+    while lengths.len() < hlit + hdist {
+        let (value, repeat) = match r.decode(&cl)? {
+            sym @ 0..=15 => (sym as u8, 1),
+            16 => match lengths.last() {
+                Some(prev) => (*prev, 3 + r.read(2)?),
+                None => return Err("length repeat with nothing before it".to_string()),
+            },
+            17 => (0, 3 + r.read(3)?),
+            _ => (0, 11 + r.read(7)?),
+        };
+        for _ in 0..repeat {
+            lengths.push(value);
+        }
+    }
+    if lengths.len() > hlit + hdist {
+        return Err("code lengths overrun".to_string());
+    }
+    if lengths[256] == 0 {
+        return Err("no end of block code".to_string());
+    }
+    return Ok((
+        Huffman::new(&lengths[..hlit])?,
+        Huffman::new(&lengths[hlit..])?,
+    ));
+}
+
+fn inflate_block(
+    r: &mut BitReader,
+    lit: &Huffman,
+    dist: &Huffman,
+    out: &mut Vec<u8>,
+    limit: usize,
+) -> Result<(), String> {
+    loop {
+        let sym = r.decode(lit)? as usize;
+        if sym < 256 {
+            if out.len() >= limit {
+                return Err(too_long(limit));
+            }
+            out.push(sym as u8);
+            continue;
+        }
+        if sym == 256 {
+            return Ok(());
+        }
+
+        let code = sym - 257;
+        if code >= LENGTH_BASE.len() {
+            return Err("invalid length code".to_string());
+        }
+        let len = LENGTH_BASE[code] as usize + r.read(LENGTH_EXTRA[code] as u32)? as usize;
+        let dcode = r.decode(dist)? as usize;
+        if dcode >= DIST_BASE.len() {
+            return Err("invalid distance code".to_string());
+        }
+        let d = DIST_BASE[dcode] as usize + r.read(DIST_EXTRA[dcode] as u32)? as usize;
+        if d > out.len() {
+            return Err("distance reaches before the start".to_string());
+        }
+        if out.len() + len > limit {
+            return Err(too_long(limit));
+        }
+
+        // the copy may overlap what it's writing, a byte at a time keeps
+        // that working
+        let start = out.len() - d;
+        for k in 0..len {
+            out.push(out[start + k]);
+        }
+    }
+}
+
+fn too_long(limit: usize) -> String {
+    return format!("decompressed data is longer than {} bytes", limit);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // small xorshift so the tests don't need the render rng
+    fn noise(n: usize, seed: u32) -> Vec<u8> {
+        let mut x = seed;
+        return (0..n)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as u8
+            })
+            .collect();
+    }
+
+    fn round_trip(data: &[u8]) {
+        let packed = compress(data);
+        assert_eq!(decompress(&packed, data.len()).unwrap(), data);
+    }
+
+    // a single stored block around the bytes
+    fn stored(data: &[u8]) -> Vec<u8> {
+        let mut out: Vec<u8> = vec![0x78, 0x01, 0x01];
+        out.extend((data.len() as u16).to_le_bytes());
+        out.extend((!(data.len() as u16)).to_le_bytes());
+        out.extend(data);
+        out.extend(adler32(data).to_be_bytes());
+        return out;
+    }
+
+    #[test]
+    fn adler32_known_value() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+        assert_eq!(adler32(b""), 1);
+    }
+
+    #[test]
+    fn compress_round_trips() {
+        round_trip(b"");
+        round_trip(b"a");
+        round_trip(b"abcabcabcabcabcabcabcabc hello hello hello");
+        round_trip(&noise(10000, 1));
+    }
+
+    #[test]
+    fn compress_long_runs_and_far_matches() {
+        // runs past the longest match, and repeats further back than the
+        // window so old chain entries get overwritten
+        let mut data: Vec<u8> = vec![7; 1000];
+        let block = noise(40000, 2);
+        data.extend(&block);
+        data.extend(&block);
+        data.extend(vec![0; 300]);
+        round_trip(&data);
+    }
+
+    #[test]
+    fn compress_shrinks_repetitive_data() {
+        let data: Vec<u8> = b"0123456789".repeat(1000);
+        assert!(compress(&data).len() < data.len() / 10);
+    }
+
+    #[test]
+    fn decompress_stops_at_the_limit() {
+        let data: Vec<u8> = vec![0; 100_000];
+        let packed = compress(&data);
+        assert!(packed.len() < 1000);
+        assert!(decompress(&packed, 1000).is_err());
+        assert_eq!(decompress(&packed, data.len()).unwrap(), data);
+
+        let packed = stored(b"hello");
+        assert!(decompress(&packed, 4).is_err());
+        assert_eq!(decompress(&packed, 5).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn decompress_rejects_corrupt_streams() {
+        let data = noise(1000, 7);
+        let packed = compress(&data);
+
+        let mut bad_sum = packed.clone();
+        *bad_sum.last_mut().unwrap() ^= 1;
+        assert!(decompress(&bad_sum, data.len()).is_err());
+
+        assert!(decompress(&packed[..packed.len() / 2], data.len()).is_err());
+        assert!(decompress(&packed[..3], data.len()).is_err());
+
+        let mut bad_header = packed.clone();
+        bad_header[0] = 0x79;
+        assert!(decompress(&bad_header, data.len()).is_err());
+    }
+}